@@ -1,10 +1,112 @@
 ﻿//This Represents the type of piece
 use std::io::{self, Write};
 use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const MIN_DIM: usize = 6;
 const MAX_DIM: usize = 12;
 
+// Game output goes through out!/outln! rather than print!/println! so execute_command can collect
+// it as text for a host program. Nothing is collected while CAPTURED_OUTPUT is None.
+thread_local! {
+    static CAPTURED_OUTPUT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn emit(text: String) {
+    CAPTURED_OUTPUT.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(buffer) => buffer.push_str(&text),
+        None => print!("{}", text),
+    });
+}
+
+macro_rules! out {
+    ($($arg:tt)*) => { emit(format!($($arg)*)) };
+}
+
+macro_rules! outln {
+    () => { emit(String::from("\n")) };
+    ($($arg:tt)*) => { emit(format!("{}\n", format_args!($($arg)*))) };
+}
+
+// English text of the translatable messages, by key. Names in braces such as {player} are filled in
+// by tr(). The command help descriptions are in HELP_COMMANDS.
+const ENGLISH_MESSAGES: [(&str, &str); 30] = [
+    ("help.title", "Available commands:"),
+    ("help.pieces", "Pieces:"),
+    ("turn.current", "Turn: {player}"),
+    ("turn.current_analysis", "Turn: {player} (analysis)"),
+    ("turn.prompt", "Type a command (type \"help\" for options):"),
+    ("turn.wins", "{player} wins! 🎉"),
+    ("turn.restart_or_exit", "Type \"restart\" to play again or \"exit\" to leave."),
+    ("turn.draw_no_progress", "Game over! It's a draw: {limit} plies without a capture (no-progress rule)."),
+    ("turn.draw_agreed", "Game over! Drawn by agreement."),
+    ("turn.draw_unknown", "Game over! It's a draw (somehow?)."),
+    ("turn.owner_gone", "{player}'s ProductOwner is gone; play continues until a side loses every piece (or both agree to a draw)."),
+    ("turn.captures", "Captures: White {white}/{target}, Black {black}/{target}"),
+    ("turn.no_progress", "No progress: {plies}/{limit} plies without a capture"),
+    ("turn.only_owner", "Only your ProductOwner can move. Type \"hint\" for the safest square."),
+    ("turn.win_alert", "You can win this turn!"),
+    ("error.unknown_command", "Unknown command: {command}\nType \"help\" to see a list of valid commands."),
+    ("error.game_over", "The game is over. Type 'restart' or 'exit'."),
+    ("error.invalid_move", "Invalid move: {reason}"),
+    ("error.hidden", "Invalid input: {square} is hidden by the fog."),
+    ("move.no_piece", "There is no piece at {square}."),
+    ("move.wrong_color", "You can't move your opponent's piece."),
+    ("move.same_square", "Destination must be different from origin."),
+    ("move.illegal_destination", "{piece} can't move to {square}."),
+    ("move.must_capture", "A capture is available, so you must capture this turn ({captures})."),
+    ("move.stale_selection", "The board changed since you selected this piece. Select it again."),
+    ("move.repeat_piece", "The piece on {square} moved last turn; move a different piece (norepeatpiece)."),
+    ("move.exposes_owner", "That move leaves your ProductOwner open to capture (strict)."),
+    ("move.capture_exposes_owner", "This capture does not resolve the threat to your ProductOwner (strict)."),
+    ("warnhang.exposes_owner", "This leaves your ProductOwner in danger. Proceed?"),
+    ("warnhang.capture_exposes_owner", "This capture does not resolve the threat to your ProductOwner. Proceed?"),
+];
+
+// The messages in use: English, with any overrides loaded from a --lang file at startup
+struct Messages {
+    texts: HashMap<String, String>,
+}
+
+static MESSAGES: OnceLock<Messages> = OnceLock::new();
+
+impl Messages {
+    fn english() -> Self {
+        let help = HELP_COMMANDS.iter().map(|(usage, text)| (help_key(usage), text.to_string()));
+        let texts = ENGLISH_MESSAGES.iter().map(|(key, text)| (key.to_string(), text.to_string())).chain(help).collect();
+        Messages { texts }
+    }
+
+    // Applies "key = text" lines over the current texts. Blank lines and lines starting with '#' are
+    // skipped, and \n in a text stands for a line break. Unknown keys are rejected so typos show up.
+    fn load_overrides(&mut self, file_text: &str) -> Result<(), String> {
+        for (i, line) in file_text.trim_start_matches('\u{feff}').lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let (key, text) = line.split_once('=').ok_or(format!("Line {}: expected \"key = text\".", i + 1))?;
+            match self.texts.get_mut(key.trim()) {
+                Some(entry) => *entry = text.trim().replace("\\n", "\n"),
+                None => return Err(format!("Line {}: unknown message key '{}'.", i + 1, key.trim())),
+            }
+        }
+        Ok(())
+    }
+}
+
+// The message for `key` with each {name} replaced by its value from `args`
+fn tr(key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let messages = MESSAGES.get_or_init(Messages::english);
+    let mut text = messages.texts.get(key).map_or(key, String::as_str).to_string();
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+    text
+}
+
 // Represents the type of piece
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum PieceType {
@@ -40,20 +142,99 @@ impl Piece {
     fn new(piece_type: PieceType, color: PlayerColor) -> Self {
         Piece { piece_type, color }
     }
+
+    // Letter used in position notation: O, D or S, uppercase for White and lowercase for Black
+    fn letter(&self) -> char {
+        let letter = match self.piece_type {
+            PieceType::ProductOwner => 'O',
+            PieceType::Developer => 'D',
+            PieceType::Designer => 'S',
+        };
+        if self.color == PlayerColor::White { letter } else { letter.to_ascii_lowercase() }
+    }
+}
+
+// Which side's letters are uppercase in position notation. White is the convention;
+// BlackUpper swaps the case of every letter, for tools that use the opposite convention.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum NotationCase {
+    #[default]
+    WhiteUpper,
+    BlackUpper,
+}
+
+impl NotationCase {
+    // Converts a letter between this convention and White-uppercase; swapping case is its own inverse,
+    // so the same call serves writing and reading
+    fn apply(self, letter: char) -> char {
+        match self {
+            NotationCase::WhiteUpper => letter,
+            NotationCase::BlackUpper if letter.is_ascii_uppercase() => letter.to_ascii_lowercase(),
+            NotationCase::BlackUpper => letter.to_ascii_uppercase(),
+        }
+    }
 }
 
-// Display trait for Piece to show Unicode characters
+// Symbol set used to draw pieces
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Theme {
+    Unicode, // Chess glyphs (default)
+    Emoji,   // Pictures; each takes two terminal columns
+    Ascii,   // Notation letters, for terminals without Unicode
+}
+
+impl Theme {
+    // The theme of the game whose command is running (see DisplayOptions::make_active)
+    fn current() -> Theme {
+        ACTIVE_DISPLAY.with(|active| active.get().theme)
+    }
+
+    // Terminal columns taken by one piece symbol
+    fn symbol_width(self) -> usize {
+        if self == Theme::Emoji { 2 } else { 1 }
+    }
+
+    fn symbol(self, piece: Piece) -> &'static str {
+        match (self, piece.piece_type, piece.color) {
+            (Theme::Unicode, PieceType::ProductOwner, PlayerColor::White) => "♔", // White PO
+            (Theme::Unicode, PieceType::Developer, PlayerColor::White)    => "♖", // White Dev
+            (Theme::Unicode, PieceType::Designer, PlayerColor::White)     => "♘", // White Des
+            (Theme::Unicode, PieceType::ProductOwner, PlayerColor::Black) => "♚", // Black PO
+            (Theme::Unicode, PieceType::Developer, PlayerColor::Black)    => "♜", // Black Dev
+            (Theme::Unicode, PieceType::Designer, PlayerColor::Black)     => "♞", // Black Des
+            (Theme::Emoji, PieceType::ProductOwner, PlayerColor::White)   => "👑",
+            (Theme::Emoji, PieceType::Developer, PlayerColor::White)      => "💻",
+            (Theme::Emoji, PieceType::Designer, PlayerColor::White)       => "🎨",
+            (Theme::Emoji, PieceType::ProductOwner, PlayerColor::Black)   => "🎩",
+            (Theme::Emoji, PieceType::Developer, PlayerColor::Black)      => "🔧",
+            (Theme::Emoji, PieceType::Designer, PlayerColor::Black)       => "📐",
+            (Theme::Ascii, PieceType::ProductOwner, PlayerColor::White)   => "O",
+            (Theme::Ascii, PieceType::Developer, PlayerColor::White)      => "D",
+            (Theme::Ascii, PieceType::Designer, PlayerColor::White)       => "S",
+            (Theme::Ascii, PieceType::ProductOwner, PlayerColor::Black)   => "o",
+            (Theme::Ascii, PieceType::Developer, PlayerColor::Black)      => "d",
+            (Theme::Ascii, PieceType::Designer, PlayerColor::Black)       => "s",
+        }
+    }
+}
+
+// Terminal columns taken by a string, counting emoji as two
+fn display_width(s: &str) -> usize {
+    s.chars().map(|ch| if ch as u32 >= 0x1F000 { 2 } else { 1 }).sum()
+}
+
+// Best guess at whether the terminal shows non-ASCII symbols, from the usual locale variables
+fn terminal_supports_unicode() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|value| { let value = value.to_uppercase(); value.contains("UTF-8") || value.contains("UTF8") })
+}
+
+// Display trait for Piece, drawn with the active theme
 impl fmt::Display for Piece {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let symbol = match (&self.piece_type, &self.color) {
-            (PieceType::ProductOwner, PlayerColor::White) => '♔', // White PO
-            (PieceType::Developer, PlayerColor::White)    => '♖', // White Dev
-            (PieceType::Designer, PlayerColor::White)     => '♘', // White Des
-            (PieceType::ProductOwner, PlayerColor::Black) => '♚', // Black PO
-            (PieceType::Developer, PlayerColor::Black)    => '♜', // Black Dev
-            (PieceType::Designer, PlayerColor::Black)     => '♞', // Black Des
-        };
-        write!(f, "{}", symbol)
+        write!(f, "{}", Theme::current().symbol(*self))
     }
 }
 
@@ -62,7 +243,7 @@ type Square = Option<Piece>;
 
 // Stores details about a potential move
 // (target_row, target_col, is_capture, Option<(jumped_piece_row, jumped_piece_col)> for Developer)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 struct MoveDetail {
     to_r: usize,
     to_c: usize,
@@ -71,75 +252,818 @@ struct MoveDetail {
 }
 
 
+// Offset table used for the Designer's L-shaped moves
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum DesignerShape {
+    #[default]
+    Standard, // (1,2)/(2,1) knight offsets
+    Extended, // Standard offsets plus the long (1,3)/(3,1) L
+}
+
+const DESIGNER_STANDARD_OFFSETS: [(isize, isize); 8] = [
+    (1, 2), (1, -2), (-1, 2), (-1, -2),
+    (2, 1), (2, -1), (-2, 1), (-2, -1),
+];
+
+const DESIGNER_EXTENDED_OFFSETS: [(isize, isize); 16] = [
+    (1, 2), (1, -2), (-1, 2), (-1, -2),
+    (2, 1), (2, -1), (-2, 1), (-2, -1),
+    (1, 3), (1, -3), (-1, 3), (-1, -3),
+    (3, 1), (3, -1), (-3, 1), (-3, -1),
+];
+
+impl DesignerShape {
+    fn offsets(&self) -> &'static [(isize, isize)] {
+        match self {
+            DesignerShape::Standard => &DESIGNER_STANDARD_OFFSETS,
+            DesignerShape::Extended => &DESIGNER_EXTENDED_OFFSETS,
+        }
+    }
+}
+
+// Which corners the two sides start in
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum SetupLayout {
+    #[default]
+    Standard, // White bottom-left, Black top-right
+    Mirrored, // White bottom-right, Black top-left
+    SameSide, // Both on the bottom rank: White left, Black right (for puzzles)
+}
+
+// Optional rule variants that change how pieces move or start
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Rules {
+    dev_land_capture: bool, // Developer may also capture by landing on an adjacent enemy
+    designer_shape: DesignerShape,
+    must_capture: bool,     // If any capture is available, only capturing moves are legal
+    setup_layout: SetupLayout,
+    capture_target: Option<usize>, // Also win by capturing this many enemy pieces
+    fog_of_war: bool,       // Players only see their own pieces and the squares those pieces can move to
+    no_progress_limit: Option<usize>, // Draw after this many plies in a row without a capture
+    play_on: bool,          // suddendeath off: losing the ProductOwner doesn't end the game; losing every piece does
+    rank_gap: Option<usize>, // closesetup: Black's back rank starts this many rows above White's instead of on the top edge
+    dev_orthogonal: bool,   // Developers move only along ranks and files, like a rook
+    handicap: Option<(PlayerColor, PieceType)>, // Extra piece for one side, placed on its back rank after the usual three
+    no_repeat_piece: bool,  // The piece a side moved last may not move again on that side's next turn
+    strict: bool,           // Moves that leave the mover's ProductOwner capturable are illegal, unless every move does
+}
+
+impl Rules {
+    // Space-separated key=value list used in the transcript's Rules tag
+    fn to_tag(self) -> String {
+        format!("devland={} mustcapture={} designer={} corners={} wincaptures={} fog={} noprogress={} suddendeath={} closesetup={} devortho={} handicap={} norepeat={} strict={}",
+            on_off(self.dev_land_capture), on_off(self.must_capture),
+            format!("{:?}", self.designer_shape).to_lowercase(), format!("{:?}", self.setup_layout).to_lowercase(),
+            self.capture_target.map_or("off".to_string(), |n| n.to_string()), on_off(self.fog_of_war),
+            self.no_progress_limit.map_or("off".to_string(), |n| n.to_string()), on_off(!self.play_on),
+            self.rank_gap.map_or("off".to_string(), |n| n.to_string()), on_off(self.dev_orthogonal),
+            self.handicap.map_or("off".to_string(), |(color, piece_type)| format!("{:?}-{:?}", color, piece_type).to_lowercase()),
+            on_off(self.no_repeat_piece), on_off(self.strict))
+    }
+
+    // Parses a Rules tag; keys that are left out keep their default
+    fn from_tag(tag: &str) -> Result<Rules, String> {
+        let mut rules = Rules::default();
+        for item in tag.split_whitespace() {
+            let (key, value) = item.split_once('=').ok_or(format!("Rule '{}' is not written as key=value.", item))?;
+            let bad_value = || format!("Rule '{}' has an unknown value '{}'.", key, value);
+            match key {
+                "devland" => rules.dev_land_capture = parse_toggle(value).ok_or_else(bad_value)?,
+                "mustcapture" => rules.must_capture = parse_toggle(value).ok_or_else(bad_value)?,
+                "designer" => rules.designer_shape = match value {
+                    "standard" => DesignerShape::Standard,
+                    "extended" => DesignerShape::Extended,
+                    _ => return Err(bad_value()),
+                },
+                "corners" => rules.setup_layout = match value {
+                    "standard" => SetupLayout::Standard,
+                    "mirrored" => SetupLayout::Mirrored,
+                    "sameside" => SetupLayout::SameSide,
+                    _ => return Err(bad_value()),
+                },
+                "wincaptures" => rules.capture_target = match value {
+                    "off" => None,
+                    n => Some(n.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(bad_value)?),
+                },
+                "fog" => rules.fog_of_war = parse_toggle(value).ok_or_else(bad_value)?,
+                "suddendeath" => rules.play_on = !parse_toggle(value).ok_or_else(bad_value)?,
+                "devortho" => rules.dev_orthogonal = parse_toggle(value).ok_or_else(bad_value)?,
+                "norepeat" => rules.no_repeat_piece = parse_toggle(value).ok_or_else(bad_value)?,
+                "strict" => rules.strict = parse_toggle(value).ok_or_else(bad_value)?,
+                "handicap" => rules.handicap = match value.split_once('-') {
+                    None if value == "off" => None,
+                    Some((color, piece)) => Some((parse_color(color).ok_or_else(bad_value)?, parse_extra_piece(piece).ok_or_else(bad_value)?)),
+                    None => return Err(bad_value()),
+                },
+                "closesetup" => rules.rank_gap = match value {
+                    "off" => None,
+                    n => Some(n.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(bad_value)?),
+                },
+                "noprogress" => rules.no_progress_limit = match value {
+                    "off" => None,
+                    n => Some(n.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(bad_value)?),
+                },
+                _ => return Err(format!("Unknown rule '{}'.", key)),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+// Reasons a move can be rejected
+#[derive(Debug, Clone, PartialEq)]
+enum MoveError {
+    GameOver,
+    NoPiece(String),                                    // Square with no piece on it
+    WrongColor,                                         // Tried to move the opponent's piece
+    SameSquare,                                         // Destination equals origin
+    IllegalDestination { piece: Piece, to: String },    // Piece can't reach that square
+    MustCapture(String),                                // Quiet move while captures are forced (lists them)
+    Hidden(String),                                     // Square hidden by fog of war
+    StaleSelection,                                     // The board changed after the piece was selected
+    RepeatPiece(String),                                // norepeatpiece: this piece moved on the side's last turn
+    ExposesOwner { capture: bool },                     // strict: leaves the ProductOwner capturable (capture: taken while it was already attacked)
+}
+
+impl MoveError {
+    // Variant name, used to tally mistakes
+    fn kind(&self) -> &'static str {
+        match self {
+            MoveError::GameOver => "GameOver",
+            MoveError::NoPiece(_) => "NoPiece",
+            MoveError::WrongColor => "WrongColor",
+            MoveError::SameSquare => "SameSquare",
+            MoveError::IllegalDestination { .. } => "IllegalDestination",
+            MoveError::MustCapture(_) => "MustCapture",
+            MoveError::Hidden(_) => "Hidden",
+            MoveError::StaleSelection => "StaleSelection",
+            MoveError::RepeatPiece(_) => "RepeatPiece",
+            MoveError::ExposesOwner { .. } => "ExposesOwner",
+        }
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            MoveError::GameOver => return write!(f, "{}", tr("error.game_over", &[])),
+            MoveError::Hidden(square) => return write!(f, "{}", tr("error.hidden", &[("square", square)])),
+            MoveError::NoPiece(square) => tr("move.no_piece", &[("square", square)]),
+            MoveError::WrongColor => tr("move.wrong_color", &[]),
+            MoveError::SameSquare => tr("move.same_square", &[]),
+            MoveError::IllegalDestination { piece, to } => tr("move.illegal_destination", &[("piece", piece), ("square", to)]),
+            MoveError::MustCapture(captures) => tr("move.must_capture", &[("captures", captures)]),
+            MoveError::StaleSelection => tr("move.stale_selection", &[]),
+            MoveError::RepeatPiece(square) => tr("move.repeat_piece", &[("square", square)]),
+            MoveError::ExposesOwner { capture: false } => tr("move.exposes_owner", &[]),
+            MoveError::ExposesOwner { capture: true } => tr("move.capture_exposes_owner", &[]),
+        };
+        write!(f, "{}", tr("error.invalid_move", &[("reason", &reason)]))
+    }
+}
+
+// The 8 straight-line directions as (row step, col step); rows grow towards Black's side (north)
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1), (-1, 0), (-1, 1),
+    (0, -1),           (0, 1),
+    (1, -1),  (1, 0),  (1, 1),
+];
+
+// Compass name of a direction, with north pointing towards the higher ranks
+fn direction_name(dr: isize, dc: isize) -> &'static str {
+    match (dr.signum(), dc.signum()) {
+        (1, 0) => "N",
+        (1, 1) => "NE",
+        (0, 1) => "E",
+        (-1, 1) => "SE",
+        (-1, 0) => "S",
+        (-1, -1) => "SW",
+        (0, -1) => "W",
+        (1, -1) => "NW",
+        _ => "-",
+    }
+}
+
+// What kind of move a legal move is (see Board::classify_move)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MoveClass {
+    Quiet,
+    LandingCapture,                // Lands on the captured piece (ProductOwner, Designer, or dev_land_capture)
+    JumpCapture((usize, usize)),   // A Developer jumping over the piece on this square
+}
+
+// Where a game stands, from game_over and winner (see GameState::result)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GameResult {
+    InProgress,
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    // Value of the transcript's Result tag
+    fn tag(self) -> &'static str {
+        match self {
+            GameResult::InProgress => "*",
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+// Result of checking one Developer destination (direction + distance)
+#[derive(Debug, Clone, Copy)]
+enum DeveloperStep {
+    Land(MoveDetail),   // Legal destination, possibly a capture
+    OffBoard,           // Destination is outside the board
+    Occupied,           // A piece stands on the destination
+    FriendlyOnPath,     // Would jump over a friendly piece
+    TwoEnemiesOnPath,   // Would jump over more than one enemy piece
+}
+
+// Rendering preferences for Board::display
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DisplayOptions {
+    color: bool,   // Allow ANSI color escape codes
+    checker: bool, // Shade the dark empty squares like a real chessboard (needs color)
+    compact: bool, // Two characters per square instead of three, for wide boards on narrow terminals
+    quiet_marker: char,   // Drawn on an empty square the selected piece can move to
+    capture_marker: char, // Drawn on a square where the selected piece would capture
+    select_brackets: (char, char), // Drawn around the selected square
+    rank_one_top: bool, // Draw rank 1 at the top; only the drawing changes, not whose side is whose
+    border: BorderStyle,
+    file_labels: (bool, bool), // File letters above and below the board
+    rank_labels: (bool, bool), // Rank numbers left and right of the board
+    theme: Theme,
+    coord_pad: bool,  // Zero-pad ranks to the board's digit count (A01 on a 10+ row board) so listed squares line up
+    // Show ranks 0-based to match the `grid` indices when debugging. Only what the player sees and types
+    // changes: board labels, listed squares and typed coordinates all follow it, so a square copied from the
+    // output can be typed back. Transcripts always use the standard 1-based ranks so they load either way.
+    zero_ranks: bool,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions { color: false, checker: false, compact: false, quiet_marker: '.', capture_marker: '•', select_brackets: ('[', ']'), rank_one_top: false,
+            border: BorderStyle::Ascii, file_labels: (true, false), rank_labels: (true, false), theme: Theme::Unicode, coord_pad: false, zero_ranks: false }
+    }
+}
+
+// Display options of the game whose command is running. Pieces and squares are printed from all over
+// (Piece's Display, coords_to_algebraic), so the theme and rank numbering are read from here rather
+// than passed down; each GameState installs its own before running a command.
+thread_local! {
+    static ACTIVE_DISPLAY: Cell<DisplayOptions> = Cell::new(DisplayOptions::default());
+}
+
+impl DisplayOptions {
+    fn make_active(&self) {
+        ACTIVE_DISPLAY.with(|active| active.set(*self));
+    }
+}
+
+// The frame drawn around the squares
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BorderStyle {
+    Ascii,   // +---+ and |
+    Unicode, // Box-drawing lines
+    None,
+}
+
+impl BorderStyle {
+    // Horizontal line, vertical line, then the top-left, top-right, bottom-left and bottom-right corners
+    fn chars(self) -> Option<(char, char, [char; 4])> {
+        match self {
+            BorderStyle::Ascii => Some(('-', '|', ['+', '+', '+', '+'])),
+            BorderStyle::Unicode => Some(('─', '│', ['┌', '┐', '└', '┘'])),
+            BorderStyle::None => None,
+        }
+    }
+}
+
+// Columns taken by a rank label; boards have at most 12 ranks
+const RANK_LABEL_WIDTH: usize = 2;
+
+// Checks a marker character takes exactly one terminal column, so board columns stay aligned
+fn parse_marker(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) if !ch.is_control() && !ch.is_whitespace() && display_width(s) == 1 => Ok(ch),
+        _ => Err(format!("Invalid input: '{}' is not a single-width character.", s)),
+    }
+}
+
+// Interaction preferences that don't change the rules
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Settings {
+    auto_select: bool, // Select the piece automatically when it is the only one that can move
+    warn_hanging: bool, // Ask before a move that leaves your ProductOwner capturable
+    tournament_scoring: bool, // Show the winner's tournament score in the game summary
+    show_progress: bool, // Show the no-progress count with the turn info
+    verbose: bool, // Explain the computer's moves
+    win_alert: bool, // Say so when the player to move can capture the enemy ProductOwner
+    lock_selection: bool, // A selected piece must be moved or deselected before other commands
+    group_moves: bool, // List a selected piece's moves by compass direction
+    echo: bool, // Print how each command was understood before running it
+    show_attackers: bool, // Say which enemy pieces attack a piece when it is selected
+}
+
+const ANSI_DARK_SQUARE: &str = "\x1b[48;5;238m";
+const ANSI_RESET: &str = "\x1b[0m";
+// Capture squares in color mode, by the value of the piece taken: the ProductOwner stands out most
+const ANSI_CAPTURE_OWNER: &str = "\x1b[1;97;41m";
+const ANSI_CAPTURE_PIECE: &str = "\x1b[30;43m";
+const ANSI_WHITE_PROMPT: &str = "\x1b[1;97m";
+const ANSI_BLACK_PROMPT: &str = "\x1b[1;90m";
+
+// A move together with the square it starts from: ((from_r, from_c), move)
+type BoardMove = ((usize, usize), MoveDetail);
+
+// Zobrist hashing: a random key per (piece type, color, square) on the largest board, XORed
+// together for every occupied square, plus a key XORed in when Black is to move
+const ZOBRIST_SQUARES: usize = MAX_DIM * MAX_DIM;
+const ZOBRIST_KEYS: [u64; 6 * ZOBRIST_SQUARES + 1] = zobrist_keys();
+const ZOBRIST_BLACK_TO_MOVE: u64 = ZOBRIST_KEYS[6 * ZOBRIST_SQUARES];
+
+// SplitMix64 generator with a fixed seed, so the keys are the same on every run
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn zobrist_keys() -> [u64; 6 * ZOBRIST_SQUARES + 1] {
+    let mut keys = [0; 6 * ZOBRIST_SQUARES + 1];
+    let mut state = 0x5EED_u64;
+    let mut i = 0;
+    while i < keys.len() {
+        keys[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    keys
+}
+
+// The session's source of randomness, seeded from --seed (or the clock) so a whole session can be
+// reproduced. Owned by the GameState and handed on to the next game on restart.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        splitmix64(&mut self.state)
+    }
+
+    // Uniform-enough index in 0..n for small n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+fn zobrist_key(piece: Piece, r: usize, c: usize) -> u64 {
+    let piece_index = piece.piece_type as usize * 2 + piece.color as usize;
+    ZOBRIST_KEYS[piece_index * ZOBRIST_SQUARES + r * MAX_DIM + c]
+}
+
+// The square (dr, dc) away from (r, c), if it is on a width x height board
+fn offset_within(r: usize, c: usize, dr: isize, dc: isize, width: usize, height: usize) -> Option<(usize, usize)> {
+    let to_r = r as isize + dr;
+    let to_c = c as isize + dc;
+    if to_r >= 0 && to_r < height as isize && to_c >= 0 && to_c < width as isize {
+        Some((to_r as usize, to_c as usize))
+    } else {
+        None
+    }
+}
+
+// On-board destinations from each square (indexed by r * width + c) for the pieces with fixed
+// move patterns, so move generation only has to check occupancy
+#[derive(Debug, PartialEq)]
+struct MoveTables {
+    owner: Vec<Vec<(usize, usize)>>,
+    designer: Vec<Vec<(usize, usize)>>,
+}
+
+impl MoveTables {
+    fn new(width: usize, height: usize, designer_shape: DesignerShape) -> Self {
+        let targets = |offsets: &[(isize, isize)]| -> Vec<Vec<(usize, usize)>> {
+            (0..width * height).map(|i| {
+                offsets.iter().filter_map(|(dr, dc)| offset_within(i / width, i % width, *dr, *dc, width, height)).collect()
+            }).collect()
+        };
+        MoveTables { owner: targets(&DIRECTIONS), designer: targets(designer_shape.offsets()) }
+    }
+}
+
 // Represents the game board
+#[derive(Clone)]
 struct Board {
     grid: Vec<Vec<Square>>,
     width: usize,
     height: usize,
+    rules: Rules,
+    hash: u64, // Zobrist hash of the pieces, updated as squares change
+    move_tables: Arc<MoveTables>, // Shared between clones; rebuilt when the size or Designer shape changes
 }
 
 impl Board {
     fn new(width: usize, height: usize) -> Self {
-        let mut board = Board {
+        let mut board = Board::empty(width, height);
+        board.setup_pieces();
+        board
+    }
+
+    // Creates a board with no pieces on it (used for scripted positions)
+    fn empty(width: usize, height: usize) -> Self {
+        let rules = Rules::default();
+        Board {
             grid: vec![vec![None; width]; height],
             width,
             height,
-        };
+            rules,
+            hash: 0,
+            move_tables: Arc::new(MoveTables::new(width, height, rules.designer_shape)),
+        }
+    }
+
+    // Starting position of a board this size under the given rules
+    fn with_rules(width: usize, height: usize, rules: Rules) -> Self {
+        let mut board = Board::empty(width, height);
+        board.rules = rules;
+        board.set_designer_shape(rules.designer_shape);
         board.setup_pieces();
         board
     }
 
-    fn setup_pieces(&mut self) {
+    fn set_designer_shape(&mut self, shape: DesignerShape) {
+        self.rules.designer_shape = shape;
+        self.move_tables = Arc::new(MoveTables::new(self.width, self.height, shape));
+    }
+
+    // Puts a piece (or nothing) on a square, keeping the hash up to date
+    fn set_square(&mut self, r: usize, c: usize, square: Square) {
+        if let Some(old) = self.grid[r][c] { self.hash ^= zobrist_key(old, r, c); }
+        if let Some(new) = square { self.hash ^= zobrist_key(new, r, c); }
+        self.grid[r][c] = square;
+    }
+
+    // Removes and returns whatever is on a square, keeping the hash up to date
+    fn take_square(&mut self, r: usize, c: usize) -> Square {
+        let square = self.grid[r][c];
+        self.set_square(r, c, None);
+        square
+    }
+
+    // Reads to_notation's format back; the board size comes from the number of ranks and squares per rank
+    fn from_notation(notation: &str) -> Result<Board, String> {
+        Board::from_notation_with(notation, NotationCase::default())
+    }
+
+    // from_notation, reading letters in the given case convention
+    fn from_notation_with(notation: &str, case: NotationCase) -> Result<Board, String> {
+        let ranks: Vec<&str> = notation.split('/').collect();
+        let mut rows = Vec::new();
+        for rank in &ranks {
+            let mut row: Vec<Square> = Vec::new();
+            let mut digits = String::new();
+            for ch in rank.chars() {
+                if ch.is_ascii_digit() { digits.push(ch); continue; }
+                if !digits.is_empty() {
+                    row.extend(std::iter::repeat_n(None, digits.parse::<usize>().map_err(|_| format!("Bad count in rank '{}'.", rank))?));
+                    digits.clear();
+                }
+                let color = if case.apply(ch).is_ascii_uppercase() { PlayerColor::White } else { PlayerColor::Black };
+                let piece_type = parse_piece_type(&ch.to_string()).ok_or(format!("Unknown piece '{}' in rank '{}'.", ch, rank))?;
+                row.push(Some(Piece::new(piece_type, color)));
+            }
+            if !digits.is_empty() {
+                row.extend(std::iter::repeat_n(None, digits.parse::<usize>().map_err(|_| format!("Bad count in rank '{}'.", rank))?));
+            }
+            rows.push(row);
+        }
+
+        let (width, height) = (rows[0].len(), rows.len());
+        if !(MIN_DIM..=MAX_DIM).contains(&width) || !(MIN_DIM..=MAX_DIM).contains(&height) {
+            return Err(format!("The board must be {}-{} squares each way, not {}x{}.", MIN_DIM, MAX_DIM, width, height));
+        }
+        if let Some(r) = rows.iter().position(|row| row.len() != width) {
+            return Err(format!("Rank {} has {} squares but the first has {}.", height - r, rows[r].len(), width));
+        }
+
+        let mut board = Board::empty(width, height);
+        for (i, row) in rows.into_iter().enumerate() {
+            for (c, square) in row.into_iter().enumerate() {
+                board.set_square(height - 1 - i, c, square);
+            }
+        }
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            let owners = board.count_pieces(color, PieceType::ProductOwner);
+            if owners != 1 { return Err(format!("{:?} has {} ProductOwners (expected 1).", color, owners)); }
+        }
+        Ok(board)
+    }
+
+    // Pieces beyond `max` per side (ProductOwner included in the count), which cull_extras would remove
+    // in this order: furthest from their own ProductOwner first, then nearest their own back rank,
+    // then from file A. The ProductOwner itself is never picked.
+    fn extra_pieces(&self, color: PlayerColor, max: usize) -> Vec<(usize, usize)> {
+        let owner = self.find_owner(color);
+        let mut pieces: Vec<(usize, usize)> = (0..self.height).flat_map(|r| (0..self.width).map(move |c| (r, c)))
+            .filter(|&(r, c)| self.grid[r][c].is_some_and(|p| p.color == color) && Some((r, c)) != owner)
+            .collect();
+        let back_rank_distance = |r: usize| if color == PlayerColor::White { r } else { self.height - 1 - r };
+        pieces.sort_by_key(|&(r, c)| {
+            let distance = owner.map_or(0, |(or, oc)| r.abs_diff(or).max(c.abs_diff(oc)));
+            (std::cmp::Reverse(distance), back_rank_distance(r), c)
+        });
+        let keep = max.saturating_sub(usize::from(owner.is_some()));
+        pieces.truncate(pieces.len().saturating_sub(keep));
+        pieces
+    }
+
+    // Removes each side's pieces beyond `max` (see extra_pieces) and returns what was removed, in order
+    fn cull_extras(&mut self, max: usize) -> Vec<(Piece, (usize, usize))> {
+        let extras: Vec<(usize, usize)> = [PlayerColor::White, PlayerColor::Black].iter()
+            .flat_map(|&color| self.extra_pieces(color, max))
+            .collect();
+        extras.into_iter().filter_map(|(r, c)| self.take_square(r, c).map(|piece| (piece, (r, c)))).collect()
+    }
+
+    // Piece placement from the top rank down, ranks separated by '/', runs of empty squares as
+    // numbers, e.g. "3ssO/6/6/6/6/ODS3" (see Piece::letter)
+    fn to_notation(&self) -> String {
+        self.to_notation_with(NotationCase::default())
+    }
+
+    // to_notation, writing letters in the given case convention
+    fn to_notation_with(&self, case: NotationCase) -> String {
+        let ranks: Vec<String> = (0..self.height).rev().map(|r| {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for square in &self.grid[r] {
+                match square {
+                    Some(piece) => {
+                        if empty > 0 { rank.push_str(&empty.to_string()); empty = 0; }
+                        rank.push(case.apply(piece.letter()));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 { rank.push_str(&empty.to_string()); }
+            rank
+        }).collect();
+        ranks.join("/")
+    }
+
+    // The grid as comma-separated rows for spreadsheets: a header of file letters, then each rank
+    // from the top down, led by its number. Cells are empty or color+type, e.g. WD or BO.
+    fn to_csv(&self) -> String {
+        let mut lines = vec![std::iter::once(String::new()).chain((0..self.width).map(|c| ((b'A' + c as u8) as char).to_string())).collect::<Vec<_>>().join(",")];
+        for r in (0..self.height).rev() {
+            let cells = self.grid[r].iter().map(|square| match square {
+                Some(piece) => format!("{}{}", if piece.color == PlayerColor::White { 'W' } else { 'B' }, piece.letter().to_ascii_uppercase()),
+                None => String::new(),
+            });
+            lines.push(std::iter::once((r + rank_base()).to_string()).chain(cells).collect::<Vec<_>>().join(","));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    // Zobrist hash of the piece placement (see position_key for the side to move)
+    fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    // Hash computed from scratch, to check the incrementally updated one
+    #[cfg(test)]
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (r, row) in self.grid.iter().enumerate() {
+            for (c, square) in row.iter().enumerate() {
+                if let Some(piece) = square { hash ^= zobrist_key(*piece, r, c); }
+            }
+        }
+        hash
+    }
+
+    // Removes every piece
+    fn clear(&mut self) {
         for r in 0..self.height {
             for c in 0..self.width {
-                self.grid[r][c] = None;
+                self.set_square(r, c, None);
+            }
+        }
+    }
+
+    fn setup_pieces(&mut self) {
+        self.clear();
+        let top_row = self.rules.rank_gap.map_or(self.height - 1, |gap| gap.min(self.height - 1));
+        // (row, starts from the left corner) for White and Black
+        let (white_corner, black_corner) = match self.rules.setup_layout {
+            SetupLayout::Standard => ((0, true), (top_row, false)),
+            SetupLayout::Mirrored => ((0, false), (top_row, true)),
+            SetupLayout::SameSide => ((0, true), (0, false)),
+        };
+        self.place_back_rank(PlayerColor::White, white_corner.0, white_corner.1);
+        self.place_back_rank(PlayerColor::Black, black_corner.0, black_corner.1);
+        if let Some((color, piece_type)) = self.rules.handicap {
+            let (row, from_left) = if color == PlayerColor::White { white_corner } else { black_corner };
+            // Fourth square of the back rank, counted from the side's corner; skipped if the board has no room
+            let col = if from_left { Some(3) } else { self.width.checked_sub(4) };
+            if let Some(col) = col.filter(|&c| c < self.width && self.grid[row][c].is_none()) {
+                self.set_square(row, col, Some(Piece::new(piece_type, color)));
+            }
+        }
+    }
+
+    // Places ProductOwner, Developer and Designer in a row, starting from the left or right corner
+    fn place_back_rank(&mut self, color: PlayerColor, row: usize, from_left: bool) {
+        let pieces = [PieceType::ProductOwner, PieceType::Developer, PieceType::Designer];
+        for (i, piece_type) in pieces.iter().enumerate() {
+            if i >= self.width { break; }
+            let col = if from_left { i } else { self.width - 1 - i };
+            self.set_square(row, col, Some(Piece::new(*piece_type, color)));
+        }
+    }
+
+    // Rows and squares that don't match the board's height and width
+    fn grid_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.grid.len() != self.height {
+            problems.push(format!("Grid has {} rows but the board height is {}.", self.grid.len(), self.height));
+        }
+        for (r, row) in self.grid.iter().enumerate() {
+            if row.len() != self.width {
+                problems.push(format!("Row {} has {} squares but the board width is {}.", r + 1, row.len(), self.width));
             }
         }
-        if self.width >= 1 { self.grid[0][0] = Some(Piece::new(PieceType::ProductOwner, PlayerColor::White)); }
-        if self.width >= 2 { self.grid[0][1] = Some(Piece::new(PieceType::Developer, PlayerColor::White)); }
-        if self.width >= 3 { self.grid[0][2] = Some(Piece::new(PieceType::Designer, PlayerColor::White)); }
+        problems
+    }
+
+    // What keeps an edited position from being played with `to_move` to move: the squares, one
+    // ProductOwner a side, and a side to move that has a move to make
+    fn setup_problems(&self, to_move: PlayerColor) -> Vec<String> {
+        let mut problems = self.grid_problems();
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            let count = self.count_pieces(color, PieceType::ProductOwner);
+            if count != 1 { problems.push(format!("{:?} has {} ProductOwners (expected 1).", color, count)); }
+        }
+        if problems.is_empty() && self.legal_moves(to_move).is_empty() {
+            problems.push(format!("{:?} is to move but has no legal moves.", to_move));
+        }
+        problems
+    }
+
+    fn count_pieces(&self, color: PlayerColor, piece_type: PieceType) -> usize {
+        self.grid.iter().flatten().filter(|sq| **sq == Some(Piece::new(piece_type, color))).count()
+    }
+
+    // Pieces left per side straight from the grid, e.g. "White: O1 D1 S1 | Black: O1 D1 S0"
+    fn material_line(&self) -> String {
+        let side = |color: PlayerColor| {
+            let counts: Vec<String> = [PieceType::ProductOwner, PieceType::Developer, PieceType::Designer].iter()
+                .map(|&piece_type| format!("{}{}", Piece::new(piece_type, PlayerColor::White).letter(), self.count_pieces(color, piece_type)))
+                .collect();
+            format!("{:?}: {}", color, counts.join(" "))
+        };
+        format!("{} | {}", side(PlayerColor::White), side(PlayerColor::Black))
+    }
+
+    fn has_pieces(&self, color: PlayerColor) -> bool {
+        self.grid.iter().flatten().any(|sq| sq.is_some_and(|p| p.color == color))
+    }
+
+    // Prints the board. With `flipped`, it is rotated 180 degrees (Black's point of view).
+    fn display(&self, selected_square: Option<(usize, usize)>, available_moves: &Option<Vec<MoveDetail>>, flipped: bool, options: &DisplayOptions) {
+        self.display_marked(selected_square, available_moves, flipped, options, &BTreeMap::new(), None);
+    }
+
+    // Like display, with extra squares drawn between a pair of marker characters instead of spaces
+    // (the selected square's [ ] brackets take priority). With a viewer, squares that player can't
+    // see under the fog-of-war rule are drawn as '?'
+    fn display_marked(&self, selected_square: Option<(usize, usize)>, available_moves: &Option<Vec<MoveDetail>>, flipped: bool, options: &DisplayOptions,
+                      marks: &BTreeMap<(usize, usize), (char, char)>, viewer: Option<PlayerColor>) {
+        out!("{}", self.render(selected_square, available_moves, flipped, options, marks, viewer));
+    }
+
+    // Squares the viewer can see under fog of war: those holding their own pieces and every
+    // square one of those pieces could move to, which reveals enemies within capture range
+    fn visible_squares(&self, viewer: PlayerColor) -> Vec<Vec<bool>> {
+        let mut visible: Vec<Vec<bool>> = self.grid.iter()
+            .map(|row| row.iter().map(|square| square.is_some_and(|p| p.color == viewer)).collect())
+            .collect();
+        for (_, m) in self.all_legal_moves(viewer) { visible[m.to_r][m.to_c] = true; }
+        visible
+    }
+
+    // Terminal columns one square takes: the symbol plus a space or marker on each side
+    // (one separator after it in compact mode)
+    fn cell_width(options: &DisplayOptions) -> usize {
+        options.theme.symbol_width() + if options.compact { 1 } else { 2 }
+    }
+
+    // The +---+ line above (top) or below the squares, as wide as a rank of cells, or None without a border
+    fn border_line(&self, options: &DisplayOptions, top: bool) -> Option<String> {
+        let (horizontal, _, corners) = options.border.chars()?;
+        let (left, right) = if top { (corners[0], corners[1]) } else { (corners[2], corners[3]) };
+        let margin = if options.rank_labels.0 { RANK_LABEL_WIDTH } else { 0 };
+        Some(format!("{}{}{}{}", " ".repeat(margin), left, horizontal.to_string().repeat(self.width * Board::cell_width(options)), right))
+    }
 
-        let top_row = self.height - 1;
-        if self.width >= 1 { self.grid[top_row][self.width - 1] = Some(Piece::new(PieceType::ProductOwner, PlayerColor::Black));}
-        if self.width >= 2 { self.grid[top_row][self.width - 2] = Some(Piece::new(PieceType::Developer, PlayerColor::Black));}
-        if self.width >= 3 { self.grid[top_row][self.width - 3] = Some(Piece::new(PieceType::Designer, PlayerColor::Black));}
+    // Rank number as drawn beside the board; coordpad only pads on boards with two-digit ranks
+    fn rank_label(&self, r: usize, options: &DisplayOptions) -> String {
+        let rank = r + if options.zero_ranks { 0 } else { 1 };
+        if options.coord_pad && self.height >= 10 { format!("{:02}", rank) } else { format!("{:>width$}", rank, width = RANK_LABEL_WIDTH) }
     }
 
-    fn display(&self, selected_square: Option<(usize, usize)>, available_moves: &Option<Vec<MoveDetail>>) {
-        println!();
-        print!("   ");
-        for c in 0..self.width { print!(" {} ", (b'A' + c as u8) as char); }
-        println!();
-        print!("  +-"); for _ in 0..self.width { print!("--"); } println!("+");
+    // The board as text, exactly as display_marked prints it
+    fn render(&self, selected_square: Option<(usize, usize)>, available_moves: &Option<Vec<MoveDetail>>, flipped: bool, options: &DisplayOptions,
+              marks: &BTreeMap<(usize, usize), (char, char)>, viewer: Option<PlayerColor>) -> String {
+        self.render_labelled(selected_square, available_moves, flipped, options, marks, viewer, &BTreeMap::new())
+    }
+
+    // render, with `labels` drawn in those empty squares in place of any move marker (used by heatmap)
+    #[allow(clippy::too_many_arguments)]
+    fn render_labelled(&self, selected_square: Option<(usize, usize)>, available_moves: &Option<Vec<MoveDetail>>, flipped: bool, options: &DisplayOptions,
+              marks: &BTreeMap<(usize, usize), (char, char)>, viewer: Option<PlayerColor>, labels: &BTreeMap<(usize, usize), char>) -> String {
+        let visible = viewer.map(|v| self.visible_squares(v));
+        let mut out = String::new();
+        let side = options.border.chars().map_or(String::new(), |(_, vertical, _)| vertical.to_string());
+        // Wide (emoji) symbols get an extra column after labels and empty squares so columns line up
+        let padding = " ".repeat(options.theme.symbol_width() - 1);
+        let mut files = " ".repeat(if options.rank_labels.0 { RANK_LABEL_WIDTH } else { 0 } + display_width(&side));
+        for c_idx in 0..self.width {
+            let c = if flipped { self.width - 1 - c_idx } else { c_idx };
+            if options.compact { files.push_str(&format!("{}{} ", (b'A' + c as u8) as char, padding)); } else { files.push_str(&format!(" {}{} ", (b'A' + c as u8) as char, padding)); }
+        }
+        out.push('\n');
+        if options.file_labels.0 { out.push_str(&format!("{}\n", files)); }
+        if let Some(border) = self.border_line(options, true) { out.push_str(&format!("{}\n", border)); }
 
         for r_rev in 0..self.height {
-            let r = self.height - 1 - r_rev;
-            print!("{:2}|", r + 1);
-            for c in 0..self.width {
-                let is_selected = selected_square.map_or(false, |(sel_r, sel_c)| sel_r == r && sel_c == c);
+            // Rank 1 is drawn first when the board is flipped or rank_one_top is on, but not both
+            let r = if flipped != options.rank_one_top { r_rev } else { self.height - 1 - r_rev };
+            if options.rank_labels.0 { out.push_str(&self.rank_label(r, options)); }
+            out.push_str(&side);
+            for c_idx in 0..self.width {
+                let c = if flipped { self.width - 1 - c_idx } else { c_idx };
+                let is_selected = selected_square.is_some_and(|(sel_r, sel_c)| sel_r == r && sel_c == c);
+                let mark = if is_selected { Some(options.select_brackets) } else { marks.get(&(r, c)).copied() };
                 let mut move_char = ' ';
+                let mut capture_value = None; // Value of the piece the move to this square would take
 
                 if let Some(moves) = available_moves {
                     for move_detail in moves {
                         if move_detail.to_r == r && move_detail.to_c == c {
-                            move_char = if move_detail.is_capture { '•' } else { '.' };
+                            move_char = if move_detail.is_capture { options.capture_marker } else { options.quiet_marker };
+                            let (taken_r, taken_c) = move_detail.jumped_piece_coord.unwrap_or((r, c));
+                            capture_value = self.get_piece(taken_r, taken_c).filter(|_| move_detail.is_capture).map(|p| piece_value(p.piece_type));
                             break;
                         }
                     }
                 }
                 
+                let hidden = visible.as_ref().is_some_and(|v| !v[r][c]);
                 let square_content = match self.grid[r][c] {
-                    Some(piece) => format!("{}", piece),
-                    None => format!("{}", move_char),
+                    _ if hidden => format!("?{}", padding),
+                    Some(piece) => options.theme.symbol(piece).to_string(),
+                    None => format!("{}{}", labels.get(&(r, c)).copied().unwrap_or(move_char), padding),
                 };
 
-                if is_selected { print!("[{}]", square_content); } 
-                else { print!(" {} ", square_content); }
+                // A1 is a dark square, as on a real chessboard
+                let shade = options.checker && options.color && self.grid[r][c].is_none() && !hidden && (r + c) % 2 == 0;
+                let highlight = capture_value.filter(|_| options.color && !hidden).map(|value| {
+                    if value >= piece_value(PieceType::ProductOwner) { ANSI_CAPTURE_OWNER } else { ANSI_CAPTURE_PIECE }
+                });
+                let background = highlight.or(Some(ANSI_DARK_SQUARE).filter(|_| shade));
+
+                let cell = if options.compact {
+                    // The selected square is marked with '<' in place of the separator, other marks with their closing character
+                    let separator = if is_selected { '<' } else { mark.map_or(' ', |(_, close)| close) };
+                    if let Some(bg) = background { format!("{}{}{}{}", bg, square_content, separator, ANSI_RESET) }
+                    else { format!("{}{}", square_content, separator) }
+                }
+                else if let Some((open, close)) = mark { format!("{}{}{}", open, square_content, close) }
+                else if let Some(bg) = background { format!("{} {} {}", bg, square_content, ANSI_RESET) }
+                else { format!(" {} ", square_content) };
+                out.push_str(&cell);
             }
-            println!("|");
+            out.push_str(&side);
+            if options.rank_labels.1 { out.push_str(&self.rank_label(r, options)); }
+            out.push('\n');
         }
-        print!("  +-"); for _ in 0..self.width { print!("--"); } println!("+");
-        println!();
+        if let Some(border) = self.border_line(options, false) { out.push_str(&format!("{}\n", border)); }
+        if options.file_labels.1 { out.push_str(&format!("{}\n", files)); }
+        out.push('\n');
+        out
     }
 
     fn get_piece(&self, r: usize, c: usize) -> Option<Piece> {
@@ -150,145 +1074,254 @@ impl Board {
         }
     }
 
+    // Returns the square (r + dr, c + dc) if it lies on the board
+    fn offset_square(&self, r: usize, c: usize, dr: isize, dc: isize) -> Option<(usize, usize)> {
+        offset_within(r, c, dr, dc, self.width, self.height)
+    }
+
     // Calculate valid moves for a piece at (start_r, start_c)
     fn calculate_valid_moves(&self, start_r: usize, start_c: usize, piece: Piece) -> Vec<MoveDetail> {
         let mut moves = Vec::new();
+        self.fill_valid_moves(start_r, start_c, piece, &mut moves);
+        moves
+    }
+
+    // Same as calculate_valid_moves, but writes into `moves` (cleared first) so a caller
+    // generating many move lists can reuse one buffer instead of allocating each time
+    fn fill_valid_moves(&self, start_r: usize, start_c: usize, piece: Piece, moves: &mut Vec<MoveDetail>) {
+        moves.clear();
         match piece.piece_type {
-            PieceType::ProductOwner => {
-                // Moves one square in any direction (8 directions)
-                for dr in -1..=1 {
-                    for dc in -1..=1 {
-                        if dr == 0 && dc == 0 { continue; } // Skip self
-
-                        let to_r_signed = start_r as isize + dr;
-                        let to_c_signed = start_c as isize + dc;
-
-                        if to_r_signed >= 0 && to_r_signed < self.height as isize &&
-                           to_c_signed >= 0 && to_c_signed < self.width as isize {
-                            let to_r = to_r_signed as usize;
-                            let to_c = to_c_signed as usize;
-
-                            match self.grid[to_r][to_c] {
-                                Some(target_piece) => {
-                                    if target_piece.color != piece.color { // Opponent piece
-                                        moves.push(MoveDetail { to_r, to_c, is_capture: true, jumped_piece_coord: None });
-                                    }
-                                    // else: friendly piece, cannot move
-                                }
-                                None => { // Empty square
-                                    moves.push(MoveDetail { to_r, to_c, is_capture: false, jumped_piece_coord: None });
-                                }
-                            }
-                        }
+            PieceType::ProductOwner | PieceType::Designer => {
+                // One square in any direction, or the Designer's L-shapes, read from the precomputed tables
+                let table = match piece.piece_type {
+                    PieceType::ProductOwner => &self.move_tables.owner,
+                    _ => &self.move_tables.designer,
+                };
+                for &(to_r, to_c) in &table[start_r * self.width + start_c] {
+                    match self.grid[to_r][to_c] {
+                        Some(target_piece) if target_piece.color == piece.color => {} // Friendly piece, cannot move
+                        target => moves.push(MoveDetail { to_r, to_c, is_capture: target.is_some(), jumped_piece_coord: None }),
                     }
                 }
             }
-            PieceType::Designer => {
-                // L-shape moves (2 in one dir, 1 perpendicular)
-                let l_moves: [(isize, isize); 8] = [
-                    (1, 2), (1, -2), (-1, 2), (-1, -2),
-                    (2, 1), (2, -1), (-2, 1), (-2, -1),
-                ];
-                for (dr, dc) in l_moves.iter() {
-                    let to_r_signed = start_r as isize + dr;
-                    let to_c_signed = start_c as isize + dc;
-
-                    if to_r_signed >= 0 && to_r_signed < self.height as isize &&
-                       to_c_signed >= 0 && to_c_signed < self.width as isize {
-                        let to_r = to_r_signed as usize;
-                        let to_c = to_c_signed as usize;
-                        match self.grid[to_r][to_c] {
-                            Some(target_piece) => {
-                                if target_piece.color != piece.color {
-                                    moves.push(MoveDetail { to_r, to_c, is_capture: true, jumped_piece_coord: None });
-                                }
-                            }
-                            None => {
-                                moves.push(MoveDetail { to_r, to_c, is_capture: false, jumped_piece_coord: None });
-                            }
+            PieceType::Developer => {
+                // Jumps up to 3 squares, any direction. Captures by jumping OVER to an EMPTY square.
+                // With dev_land_capture, an enemy on the adjacent square can also be captured by landing on it.
+                for (dr_base, dc_base) in DIRECTIONS.iter().filter(|(dr, dc)| self.developer_direction_allowed(*dr, *dc)) {
+                    for dist in 1..=3 { // Distance 1, 2, or 3
+                        match self.developer_step(start_r, start_c, piece, *dr_base, *dc_base, dist) {
+                            DeveloperStep::Land(move_detail) => moves.push(move_detail),
+                            DeveloperStep::OffBoard => break, // Off board, stop this direction
+                            _ => continue, // Can't land here, try the next distance
                         }
                     }
                 }
             }
-            PieceType::Developer => {
-                // Jumps up to 3 squares, any direction. Captures by jumping OVER to an EMPTY square.
-                for dr_base in -1..=1 { // Direction vector row component
-                    for dc_base in -1..=1 { // Direction vector col component
-                        if dr_base == 0 && dc_base == 0 { continue; } // Skip no direction
+        }
+    }
 
-                        for dist in 1..=3 { // Distance 1, 2, or 3
-                            let to_r_signed = start_r as isize + dr_base * dist;
-                            let to_c_signed = start_c as isize + dc_base * dist;
+    // The unit direction and distance of a straight Developer move from one square to another
+    // (the (dr_base, dc_base) and dist of fill_valid_moves), or None if no Developer move could join them
+    fn developer_vector(from: (usize, usize), to: (usize, usize)) -> Option<(isize, isize, isize)> {
+        let dr = to.0 as isize - from.0 as isize;
+        let dc = to.1 as isize - from.1 as isize;
+        let dist = dr.abs().max(dc.abs());
+        let straight = (dr == 0 || dc == 0 || dr.abs() == dc.abs()) && (1..=3).contains(&dist);
+        Some((dr / dist.max(1), dc / dist.max(1), dist)).filter(|_| straight)
+    }
 
-                            if to_r_signed < 0 || to_r_signed >= self.height as isize ||
-                               to_c_signed < 0 || to_c_signed >= self.width as isize {
-                                break; // Off board, stop this direction
-                            }
-                            let to_r = to_r_signed as usize;
-                            let to_c = to_c_signed as usize;
+    // Diagonals are skipped under the dev_orthogonal rule
+    fn developer_direction_allowed(&self, dr: isize, dc: isize) -> bool {
+        !self.rules.dev_orthogonal || dr == 0 || dc == 0
+    }
 
-                            // Target square must be empty for Developer
-                            if self.grid[to_r][to_c].is_some() {
-                                break; // Blocked by piece on target, stop this direction
-                            }
+    // Outcome of a Developer moving `dist` squares in direction (dr_base, dc_base)
+    fn developer_step(&self, start_r: usize, start_c: usize, piece: Piece, dr_base: isize, dc_base: isize, dist: isize) -> DeveloperStep {
+        let (to_r, to_c) = match self.offset_square(start_r, start_c, dr_base * dist, dc_base * dist) {
+            Some(coords) => coords,
+            None => return DeveloperStep::OffBoard,
+        };
 
-                            // Check path for jumped piece
-                            let mut jumped_piece_on_path: Option<(usize, usize)> = None;
-                            let mut path_blocked_by_friendly = false;
-                            let mut multiple_opponents_on_path = false;
-
-                            if dist > 1 { // Only need to check path if jumping (dist 2 or 3)
-                                for step in 1..dist { // Iterate over squares between start and target
-                                    let path_r = (start_r as isize + dr_base * step) as usize;
-                                    let path_c = (start_c as isize + dc_base * step) as usize;
-                                    if let Some(path_piece) = self.grid[path_r][path_c] {
-                                        if path_piece.color == piece.color {
-                                            path_blocked_by_friendly = true;
-                                            break;
-                                        } else { // Opponent piece on path
-                                            if jumped_piece_on_path.is_some() {
-                                                multiple_opponents_on_path = true; // Second opponent on path
-                                                break;
-                                            }
-                                            jumped_piece_on_path = Some((path_r, path_c));
-                                        }
-                                    }
-                                }
-                            }
+        // Target square must be empty for Developer (except the adjacent landing capture variant)
+        if let Some(target_piece) = self.grid[to_r][to_c] {
+            if dist == 1 && self.rules.dev_land_capture && target_piece.color != piece.color {
+                return DeveloperStep::Land(MoveDetail { to_r, to_c, is_capture: true, jumped_piece_coord: None });
+            }
+            return DeveloperStep::Occupied; // Can't land here, but may still jump over it
+        }
 
-                            if path_blocked_by_friendly || multiple_opponents_on_path {
-                                continue; // Path is blocked, try next distance or direction
-                            }
-                            
-                            // If target is empty and path is valid:
-                            let is_capture = jumped_piece_on_path.is_some();
-                            moves.push(MoveDetail { to_r, to_c, is_capture, jumped_piece_coord: jumped_piece_on_path });
+        // Check path for jumped piece (only needed when jumping, dist 2 or 3)
+        let mut jumped_piece_on_path: Option<(usize, usize)> = None;
+        for step in 1..dist { // Iterate over squares between start and target
+            // Checked independently so the scan never relies on the target being on-board
+            let (path_r, path_c) = match self.offset_square(start_r, start_c, dr_base * step, dc_base * step) {
+                Some(coords) => coords,
+                None => return DeveloperStep::OffBoard,
+            };
+            if let Some(path_piece) = self.get_piece(path_r, path_c) {
+                if path_piece.color == piece.color {
+                    return DeveloperStep::FriendlyOnPath;
+                } else { // Opponent piece on path
+                    if jumped_piece_on_path.is_some() {
+                        return DeveloperStep::TwoEnemiesOnPath; // Second opponent on path
+                    }
+                    jumped_piece_on_path = Some((path_r, path_c));
+                }
+            }
+        }
+
+        // If target is empty and path is valid:
+        let is_capture = jumped_piece_on_path.is_some();
+        DeveloperStep::Land(MoveDetail { to_r, to_c, is_capture, jumped_piece_coord: jumped_piece_on_path })
+    }
+
+    // Straightforward move generator with no precomputed tables, buffer reuse or shared helpers,
+    // kept as the reference that --verify checks calculate_valid_moves against
+    fn reference_valid_moves(&self, start_r: usize, start_c: usize, piece: Piece) -> Vec<MoveDetail> {
+        let mut moves = Vec::new();
+        let offsets: &[(isize, isize)] = match piece.piece_type {
+            PieceType::ProductOwner => &DIRECTIONS,
+            PieceType::Designer => self.rules.designer_shape.offsets(),
+            PieceType::Developer => {
+                for &(dr, dc) in DIRECTIONS.iter().filter(|(dr, dc)| !self.rules.dev_orthogonal || *dr == 0 || *dc == 0) {
+                    for dist in 1..=3 {
+                        let Some((to_r, to_c)) = self.offset_square(start_r, start_c, dr * dist, dc * dist) else { break; };
+                        let on_path: Vec<(usize, usize)> = (1..dist)
+                            .filter_map(|step| self.offset_square(start_r, start_c, dr * step, dc * step))
+                            .filter(|&(r, c)| self.grid[r][c].is_some())
+                            .collect();
+                        match (self.grid[to_r][to_c], on_path.as_slice()) {
+                            (Some(target), _) if dist == 1 && self.rules.dev_land_capture && target.color != piece.color =>
+                                moves.push(MoveDetail { to_r, to_c, is_capture: true, jumped_piece_coord: None }),
+                            (Some(_), _) => {}
+                            (None, []) => moves.push(MoveDetail { to_r, to_c, is_capture: false, jumped_piece_coord: None }),
+                            (None, [(r, c)]) if self.grid[*r][*c].is_some_and(|p| p.color != piece.color) =>
+                                moves.push(MoveDetail { to_r, to_c, is_capture: true, jumped_piece_coord: Some((*r, *c)) }),
+                            (None, _) => {}
                         }
                     }
                 }
+                return moves;
+            }
+        };
+        for &(dr, dc) in offsets {
+            if let Some((to_r, to_c)) = self.offset_square(start_r, start_c, dr, dc) {
+                match self.grid[to_r][to_c] {
+                    Some(target) if target.color == piece.color => {}
+                    target => moves.push(MoveDetail { to_r, to_c, is_capture: target.is_some(), jumped_piece_coord: None }),
+                }
             }
         }
         moves
     }
 
-    // Attempts to move a piece. Returns Ok(Option<Piece>) with captured piece if successful, Err(String) otherwise.
-    fn move_piece(&mut self, from_r: usize, from_c: usize, to_r: usize, to_c: usize, current_player: PlayerColor, valid_moves: &[MoveDetail]) -> Result<Option<Piece>, String> {
+    // Classifies the move of whatever piece stands on `from` to `to`, or None if it isn't a legal move
+    // for that piece. Doesn't apply it, and ignores whose turn it is and the must-capture rule.
+    fn classify_move(&self, from: (usize, usize), to: (usize, usize)) -> Option<MoveClass> {
+        let piece = self.get_piece(from.0, from.1)?;
+        let m = self.calculate_valid_moves(from.0, from.1, piece).into_iter().find(|m| (m.to_r, m.to_c) == to)?;
+        Some(match m.jumped_piece_coord {
+            Some(jumped) => MoveClass::JumpCapture(jumped),
+            None if m.is_capture => MoveClass::LandingCapture,
+            None => MoveClass::Quiet,
+        })
+    }
+
+    // Collects every legal move for the given color as ((from_r, from_c), move)
+    fn all_legal_moves(&self, color: PlayerColor) -> Vec<BoardMove> {
+        let mut all_moves = Vec::new();
+        let mut piece_moves = Vec::new();
+        for r in 0..self.height {
+            for c in 0..self.width {
+                if let Some(piece) = self.grid[r][c].filter(|p| p.color == color) {
+                    self.fill_valid_moves(r, c, piece, &mut piece_moves);
+                    all_moves.extend(piece_moves.iter().map(|m| ((r, c), *m)));
+                }
+            }
+        }
+        all_moves
+    }
+
+    // Collects every capturing move for the given color
+    fn capturing_moves(&self, color: PlayerColor) -> Vec<BoardMove> {
+        self.all_legal_moves(color).into_iter().filter(|(_, m)| m.is_capture).collect()
+    }
+
+    // Capturing moves for `color` that take the enemy ProductOwner, Developer jumps over it included
+    fn owner_captures(&self, color: PlayerColor) -> Vec<BoardMove> {
+        let Some(owner) = self.find_owner(color.opponent()) else { return Vec::new(); };
+        self.capturing_moves(color).into_iter().filter(|(_, m)| m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c)) == owner).collect()
+    }
+
+    // Square of the given side's ProductOwner, if it is still on the board
+    fn find_owner(&self, color: PlayerColor) -> Option<(usize, usize)> {
+        let owner = Some(Piece::new(PieceType::ProductOwner, color));
+        (0..self.height).flat_map(|r| (0..self.width).map(move |c| (r, c))).find(|&(r, c)| self.grid[r][c] == owner)
+    }
+
+    // Number of friendly pieces that could recapture if the piece on (r, c) were taken
+    fn defenders(&self, r: usize, c: usize) -> usize {
+        let piece = match self.grid[r][c] {
+            Some(p) => p,
+            None => return 0,
+        };
+        // Swap in an enemy piece and see which of our pieces could capture it
+        let mut probe = self.clone();
+        probe.set_square(r, c, Some(Piece::new(piece.piece_type, piece.color.opponent())));
+        probe.attackers(r, c, piece.color)
+    }
+
+    // Number of distinct `by` pieces that could capture whatever stands on (r, c)
+    fn attackers(&self, r: usize, c: usize, by: PlayerColor) -> usize {
+        self.attacker_squares(r, c, by).len()
+    }
+
+    // Squares of the distinct `by` pieces that could capture whatever stands on (r, c)
+    fn attacker_squares(&self, r: usize, c: usize, by: PlayerColor) -> Vec<(usize, usize)> {
+        let mut attackers: Vec<(usize, usize)> = self.capturing_moves(by).into_iter()
+            .filter(|(_, m)| m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c)) == (r, c))
+            .map(|(from, _)| from)
+            .collect();
+        attackers.dedup();
+        attackers
+    }
+
+    // Key identifying the position (pieces and side to move) for the AI's transposition table
+    fn position_key(&self, to_move: PlayerColor) -> u64 {
+        match to_move {
+            PlayerColor::White => self.hash(),
+            PlayerColor::Black => self.hash() ^ ZOBRIST_BLACK_TO_MOVE,
+        }
+    }
+
+    // Every move the color may make once game rules such as mustcapture are applied
+    fn legal_moves(&self, color: PlayerColor) -> Vec<BoardMove> {
+        let moves = self.all_legal_moves(color);
+        if self.rules.must_capture && moves.iter().any(|(_, m)| m.is_capture) {
+            moves.into_iter().filter(|(_, m)| m.is_capture).collect()
+        } else {
+            moves
+        }
+    }
+
+    // Attempts to move a piece. Returns Ok(Option<Piece>) with captured piece if successful, Err(MoveError) otherwise.
+    fn move_piece(&mut self, from_r: usize, from_c: usize, to_r: usize, to_c: usize, current_player: PlayerColor, valid_moves: &[MoveDetail]) -> Result<Option<Piece>, MoveError> {
         let moving_piece_option = self.get_piece(from_r, from_c);
 
         // Validation 1: Is there a piece at 'from'?
         let moving_piece = match moving_piece_option {
             Some(p) => p,
-            None => return Err(format!("Invalid move: There is no piece at {}.", coords_to_algebraic(from_r, from_c, self.height))),
+            None => return Err(MoveError::NoPiece(coords_to_algebraic(from_r, from_c, self.height))),
         };
 
         // Validation 2: Is it the current player's piece?
         if moving_piece.color != current_player {
-            return Err("Invalid move: You can't move your opponent's piece.".to_string());
+            return Err(MoveError::WrongColor);
         }
 
         // Validation 3: Is 'to' different from 'from'?
         if from_r == to_r && from_c == to_c {
-            return Err("Invalid move: Destination must be different from origin.".to_string());
+            return Err(MoveError::SameSquare);
         }
 
         // Validation 4: Is the move in the list of valid moves for the selected piece?
@@ -296,37 +1329,547 @@ impl Board {
         
         let valid_move_info = match move_detail {
             Some(m_info) => m_info,
-            None => return Err(format!("Invalid move: {} can't move to {}.", moving_piece, coords_to_algebraic(to_r, to_c, self.height))),
+            None => return Err(MoveError::IllegalDestination { piece: moving_piece, to: coords_to_algebraic(to_r, to_c, self.height) }),
         };
 
         // Perform the move
-        self.grid[from_r][from_c] = None; // Remove piece from original square
-        let mut captured_piece_details: Option<Piece> = None;
-
-        if valid_move_info.is_capture {
-            match moving_piece.piece_type {
-                PieceType::Developer => {
-                    // Developer captures by jumping over, target square is empty.
-                    // The piece to remove is at valid_move_info.jumped_piece_coord.
-                    if let Some((jumped_r, jumped_c)) = valid_move_info.jumped_piece_coord {
-                        captured_piece_details = self.grid[jumped_r][jumped_c].take(); // Take the jumped piece
-                    } else {
-                        // This should not happen if is_capture is true for Developer based on calculate_valid_moves
-                        return Err("Internal error: Developer capture indicated but no jumped piece coordinate.".to_string());
-                    }
-                }
-                PieceType::Designer | PieceType::ProductOwner => {
-                    // These pieces capture by landing on the opponent's piece.
-                    captured_piece_details = self.grid[to_r][to_c].take(); // Take the piece at the destination
-                }
+        Ok(self.apply_move(from_r, from_c, valid_move_info))
+    }
+
+    // True if making move `m` from (from_r, from_c) leaves `color`'s ProductOwner open to capture.
+    // A move that captures the enemy ProductOwner never does.
+    fn exposes_owner(&self, from_r: usize, from_c: usize, m: &MoveDetail, color: PlayerColor) -> bool {
+        let mut next = self.clone();
+        if next.apply_move(from_r, from_c, m).is_some_and(|p| p.piece_type == PieceType::ProductOwner) { return false; }
+        next.find_owner(color).is_some_and(|(r, c)| next.attackers(r, c, color.opponent()) > 0)
+    }
+
+    // Plays a move already known to be legal and returns the captured piece, if any
+    fn apply_move(&mut self, from_r: usize, from_c: usize, m: &MoveDetail) -> Option<Piece> {
+        let moving_piece = self.take_square(from_r, from_c); // Remove piece from original square
+        let captured = match m.jumped_piece_coord {
+            Some((jumped_r, jumped_c)) => self.take_square(jumped_r, jumped_c), // Developer jumps over its capture
+            None => self.take_square(m.to_r, m.to_c), // Landing capture (None for a quiet move)
+        };
+        self.set_square(m.to_r, m.to_c, moving_piece); // Place moving piece at destination
+        captured
+    }
+}
+
+// Material value of each piece type for the AI evaluation
+fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::ProductOwner => 1000,
+        PieceType::Designer => 3,
+        PieceType::Developer => 3,
+    }
+}
+
+// How much each evaluation term counts; material is scaled up so one legal move or one step
+// towards the centre is worth a tenth of a point
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct EvalWeights {
+    material: i32,
+    mobility: i32,     // Per legal move more than the opponent has
+    center: i32,       // Per step a non-ProductOwner piece stands away from the board edge
+    owner_safety: i32, // Per piece attacking the enemy ProductOwner, minus those attacking ours
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights { material: 10, mobility: 1, center: 1, owner_safety: 5 }
+    }
+}
+
+// The evaluation terms before weighting, each from `color`'s point of view (shown by "eval")
+#[derive(Debug, Clone, Copy, Default)]
+struct EvalTerms {
+    material: i32,
+    mobility: i32,
+    center: i32,
+    owner_safety: i32,
+}
+
+impl EvalTerms {
+    fn weighted(&self, weights: &EvalWeights) -> i32 {
+        self.material * weights.material + self.mobility * weights.mobility
+            + self.center * weights.center + self.owner_safety * weights.owner_safety
+    }
+}
+
+fn eval_terms(board: &Board, color: PlayerColor) -> EvalTerms {
+    let sign = |p: &Piece| if p.color == color { 1 } else { -1 };
+    let material = board.grid.iter().flatten().flatten().map(|p| sign(p) * piece_value(p.piece_type)).sum();
+
+    let mut center = 0;
+    for (r, row) in board.grid.iter().enumerate() {
+        for (c, square) in row.iter().enumerate() {
+            if let Some(p) = square.filter(|p| p.piece_type != PieceType::ProductOwner) {
+                center += sign(&p) * (r.min(board.height - 1 - r) + c.min(board.width - 1 - c)) as i32;
             }
         }
-        
-        self.grid[to_r][to_c] = Some(moving_piece); // Place moving piece at destination
-        Ok(captured_piece_details)
+    }
+
+    // Distinct pieces in `moves` that could capture on `square`
+    let threats = |moves: &[BoardMove], square: Option<(usize, usize)>| {
+        let mut from: Vec<(usize, usize)> = moves.iter()
+            .filter(|(_, m)| m.is_capture && Some(m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c))) == square)
+            .map(|(from, _)| *from)
+            .collect();
+        from.dedup();
+        from.len() as i32
+    };
+    let ours = board.all_legal_moves(color);
+    let theirs = board.all_legal_moves(color.opponent());
+    EvalTerms {
+        material,
+        mobility: ours.len() as i32 - theirs.len() as i32,
+        center,
+        owner_safety: threats(&ours, board.find_owner(color.opponent())) - threats(&theirs, board.find_owner(color)),
+    }
+}
+
+// Weighted evaluation from `color`'s point of view
+fn evaluate(board: &Board, color: PlayerColor, weights: &EvalWeights) -> i32 {
+    eval_terms(board, color).weighted(weights)
+}
+
+// Tournament score for a win: TOURNAMENT_WIN_POINTS plus TOURNAMENT_MATERIAL_WEIGHT times the material
+// the winner still has on the board (piece_value, ProductOwner left out). The loser scores 0.
+// Raise the weight to make winning margins count for more in tie-breaks.
+const TOURNAMENT_WIN_POINTS: i32 = 10;
+const TOURNAMENT_MATERIAL_WEIGHT: i32 = 1;
+
+// Value of the pieces `color` still has, not counting its ProductOwner
+fn surviving_material(board: &Board, color: PlayerColor) -> i32 {
+    board.grid.iter().flatten().flatten()
+        .filter(|p| p.color == color && p.piece_type != PieceType::ProductOwner)
+        .map(|p| piece_value(p.piece_type))
+        .sum()
+}
+
+fn tournament_score(board: &Board, winner: PlayerColor) -> i32 {
+    TOURNAMENT_WIN_POINTS + TOURNAMENT_MATERIAL_WEIGHT * surviving_material(board, winner)
+}
+
+// An AI config written as comma-separated settings such as "depth=2" or "mobility=5,center=0"
+// (terms as in "ai weight"), each changing `base`
+fn parse_ai_config(spec: &str, base: AiConfig) -> Result<AiConfig, String> {
+    let mut config = base;
+    for item in spec.split(',') {
+        let (key, value) = item.split_once('=').ok_or(format!("Invalid input: '{}' should look like depth=3 or mobility=5.", item))?;
+        let value = value.parse::<i32>().map_err(|_| format!("Invalid input: '{}' is not a number.", value))?;
+        let weights = &mut config.weights;
+        let term = match key.to_lowercase().as_str() {
+            "depth" if (1..=MAX_AI_DEPTH as i32).contains(&value) => { config.depth = value as u32; continue; }
+            "depth" => return Err(format!("Invalid input: depth must be from 1 to {}.", MAX_AI_DEPTH)),
+            "material" => &mut weights.material,
+            "mobility" => &mut weights.mobility,
+            "center" => &mut weights.center,
+            "safety" => &mut weights.owner_safety,
+            other => return Err(format!("Invalid input: unknown setting '{}' (depth, material, mobility, center or safety).", other)),
+        };
+        if !(0..=100).contains(&value) { return Err(format!("Invalid input: {} must be from 0 to 100.", key)); }
+        *term = value;
+    }
+    Ok(config)
+}
+
+// Engine matches: random plies played before the computers take over, and the length at which a game is drawn
+const MATCH_OPENING_PLIES: usize = 2;
+const MAX_MATCH_PLIES: usize = 200;
+
+// Computer player settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AiConfig {
+    time_budget_ms: u64, // Stop searching after this long and play the best move found so far
+    depth: u32,          // Deepest search, in plies
+    use_table: bool,     // Remember positions already searched (transposition table)
+    weights: EvalWeights,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        AiConfig { time_budget_ms: 2000, depth: 3, use_table: true, weights: EvalWeights::default() }
+    }
+}
+
+const MAX_AI_DEPTH: u32 = 8;
+const WIN_SCORE: i32 = 1_000_000; // Capturing the Product Owner; reduced by ply so quicker wins score higher
+const INFINITE_SCORE: i32 = WIN_SCORE + 1;
+
+// How a stored score relates to the true score of the position
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    Lower, // Search failed high: the true score is at least this
+    Upper, // Search failed low: the true score is at most this
+}
+
+// Transposition table entry: the result of searching a position to some depth
+#[derive(Debug, Clone, Copy)]
+struct TableEntry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+}
+
+// Counters from the AI's last move, shown by "ai stats"
+#[derive(Debug, Clone, Copy, Default)]
+struct SearchStats {
+    nodes: u64,
+    depth_reached: u32,
+    table_probes: u64,
+    table_hits: u64,
+    score: Option<i32>, // Evaluation of the chosen move at the deepest finished depth
+    reason: Option<AiReason>, // Why the chosen move was picked
+}
+
+// The main reason behind the AI's choice, shown after its move in verbose mode and by "ai stats"
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AiReason {
+    OnlyMove,
+    CapturesOwner,
+    ForcedWin(i32),  // Plies until the enemy ProductOwner falls
+    ForcedLoss(i32), // Plies until ours falls, with the longest defence
+    Capture(PieceType, i32), // Captured piece and the search score
+    BestScore(i32),
+    NoSearch, // Not even one ply finished in time; the first candidate was played
+}
+
+impl fmt::Display for AiReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AiReason::OnlyMove => write!(f, "only legal move"),
+            AiReason::CapturesOwner => write!(f, "captures the ProductOwner"),
+            AiReason::ForcedWin(plies) => write!(f, "forces the capture of the ProductOwner within {} plies", plies),
+            AiReason::ForcedLoss(plies) => write!(f, "every move loses; this one holds out longest ({} plies)", plies),
+            AiReason::Capture(piece_type, score) => write!(f, "captures a {:?} (+{} material), search score {:+}", piece_type, piece_value(*piece_type), score),
+            AiReason::BestScore(score) => write!(f, "best search score {:+}", score),
+            AiReason::NoSearch => write!(f, "out of time before any search finished"),
+        }
+    }
+}
+
+fn ai_reason(board: &Board, candidate_count: usize, (_, m): &BoardMove, score: Option<i32>) -> AiReason {
+    let captured = Some(m).filter(|m| m.is_capture).and_then(|m| {
+        let (r, c) = m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c));
+        board.get_piece(r, c)
+    });
+    match score {
+        _ if candidate_count == 1 => AiReason::OnlyMove,
+        _ if captured.is_some_and(|p| p.piece_type == PieceType::ProductOwner) => AiReason::CapturesOwner,
+        Some(s) if s >= WIN_SCORE - MAX_AI_DEPTH as i32 => AiReason::ForcedWin(WIN_SCORE - s + 1),
+        Some(s) if s <= -(WIN_SCORE - MAX_AI_DEPTH as i32) => AiReason::ForcedLoss(WIN_SCORE + s + 1),
+        Some(s) => captured.map_or(AiReason::BestScore(s), |p| AiReason::Capture(p.piece_type, s)),
+        None => AiReason::NoSearch,
+    }
+}
+
+// Search bookkeeping shared by every node of one AI move
+struct SearchContext {
+    deadline: Instant,
+    nodes: u64,
+    timed_out: bool,
+    table: Option<HashMap<u64, TableEntry>>, // None when the transposition table is off
+    table_probes: u64,
+    table_hits: u64,
+    weights: EvalWeights,
+}
+
+// Win scores count plies from the root; the table stores them counted from the position instead
+fn score_to_table(score: i32, ply: i32) -> i32 {
+    if score >= WIN_SCORE - MAX_AI_DEPTH as i32 { score + ply }
+    else if score <= -(WIN_SCORE - MAX_AI_DEPTH as i32) { score - ply }
+    else { score }
+}
+
+fn score_from_table(score: i32, ply: i32) -> i32 {
+    if score >= WIN_SCORE - MAX_AI_DEPTH as i32 { score - ply }
+    else if score <= -(WIN_SCORE - MAX_AI_DEPTH as i32) { score + ply }
+    else { score }
+}
+
+impl SearchContext {
+    // Counts a node and reports whether the time budget has run out (the clock is read every 1024 nodes)
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes & 1023 == 0 && Instant::now() >= self.deadline { self.timed_out = true; }
+        self.timed_out
+    }
+}
+
+// Legal moves with captures first, so alpha-beta finds cutoffs sooner
+fn ordered_moves(board: &Board, color: PlayerColor) -> Vec<BoardMove> {
+    let mut moves = board.legal_moves(color);
+    moves.sort_by_key(|(_, m)| !m.is_capture);
+    moves
+}
+
+// Score of playing `m` for `color`: a Product Owner capture ends the game, otherwise the
+// opponent's best reply is searched. None if the time budget ran out.
+fn score_move(board: &Board, color: PlayerColor, (from, m): &BoardMove, depth: u32, ply: i32, (alpha, beta): (i32, i32), ctx: &mut SearchContext) -> Option<i32> {
+    let mut next = board.clone();
+    match next.apply_move(from.0, from.1, m) {
+        Some(p) if p.piece_type == PieceType::ProductOwner => Some(WIN_SCORE - ply),
+        _ => negamax(&next, color.opponent(), depth - 1, ply + 1, -beta, -alpha, ctx).map(|s| -s),
+    }
+}
+
+// Negamax search with alpha-beta pruning, scored from `color`'s point of view.
+// Positions already searched at least as deep are answered from the transposition table.
+fn negamax(board: &Board, color: PlayerColor, depth: u32, ply: i32, mut alpha: i32, mut beta: i32, ctx: &mut SearchContext) -> Option<i32> {
+    if ctx.out_of_time() { return None; }
+    if depth == 0 { return Some(evaluate(board, color, &ctx.weights)); }
+
+    let key = board.position_key(color);
+    let original_alpha = alpha;
+    if let Some(table) = &ctx.table {
+        ctx.table_probes += 1;
+        if let Some(entry) = table.get(&key).filter(|e| e.depth >= depth) {
+            ctx.table_hits += 1;
+            let score = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return Some(score),
+                Bound::Lower => alpha = alpha.max(score),
+                Bound::Upper => beta = beta.min(score),
+            }
+            if alpha >= beta { return Some(score); }
+        }
+    }
+
+    let moves = ordered_moves(board, color);
+    if moves.is_empty() { return Some(evaluate(board, color, &ctx.weights)); }
+
+    let mut best = -INFINITE_SCORE;
+    for mv in &moves {
+        let score = score_move(board, color, mv, depth, ply, (alpha, beta), ctx)?;
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta { break; }
+    }
+
+    if let Some(table) = &mut ctx.table {
+        let bound = if best <= original_alpha { Bound::Upper } else if best >= beta { Bound::Lower } else { Bound::Exact };
+        table.insert(key, TableEntry { depth, score: score_to_table(best, ply), bound });
+    }
+    Some(best)
+}
+
+// Picks a move by iterative deepening: searches 1 ply, then 2, and so on up to the configured
+// depth. The previous depth's best move is tried first.
+// Stops once the time budget runs out and keeps the result of the last finished depth.
+fn choose_ai_move(board: &Board, color: PlayerColor, candidates: &[BoardMove], config: &AiConfig) -> (Option<BoardMove>, SearchStats) {
+    let started = Instant::now();
+    let budget = Duration::from_millis(config.time_budget_ms);
+    let mut ctx = SearchContext {
+        deadline: started + budget,
+        nodes: 0,
+        timed_out: false,
+        table: if config.use_table { Some(HashMap::new()) } else { None },
+        table_probes: 0,
+        table_hits: 0,
+        weights: config.weights,
+    };
+    let mut depth_reached = 0;
+    let mut best_score = None;
+    let mut root_moves = candidates.to_vec();
+    root_moves.sort_by_key(|(_, m)| !m.is_capture);
+    let mut best: Option<BoardMove> = None;
+
+    for depth in 1..=config.depth.max(1) {
+        // Each depth takes several times as long as the one before, so once half the budget is used
+        // the next would only be cut off and thrown away: stop with the last completed depth instead
+        if depth > 1 && started.elapsed() * 2 >= budget { break; }
+        let mut alpha = -INFINITE_SCORE;
+        let mut depth_best: Option<(usize, i32)> = None;
+        for (i, mv) in root_moves.iter().enumerate() {
+            let score = match score_move(board, color, mv, depth, 0, (alpha, INFINITE_SCORE), &mut ctx) {
+                Some(score) => score,
+                None => break,
+            };
+            if score > alpha {
+                alpha = score;
+                depth_best = Some((i, score));
+            }
+        }
+
+        if ctx.timed_out {
+            // An unfinished depth is only trusted when nothing better is known
+            if best.is_none() { best = depth_best.map(|(i, _)| root_moves[i]); }
+            break;
+        }
+        let (i, score) = match depth_best {
+            Some(found) => found,
+            None => break,
+        };
+        let mv = root_moves.remove(i);
+        root_moves.insert(0, mv);
+        best = Some(mv);
+        depth_reached = depth;
+        best_score = Some(score);
+        if score.abs() >= WIN_SCORE - MAX_AI_DEPTH as i32 { break; } // Forced win or loss found
+    }
+
+    let best = best.or_else(|| candidates.first().copied());
+    let reason = best.map(|mv| ai_reason(board, candidates.len(), &mv, best_score));
+    let stats = SearchStats { nodes: ctx.nodes, depth_reached, table_probes: ctx.table_probes, table_hits: ctx.table_hits, score: best_score, reason };
+    (best, stats)
+}
+
+// Runs choose_ai_move for `color` on a background thread with its own copy of the board.
+// The chosen move and search counters arrive on the returned channel when the search ends.
+fn spawn_search(board: &Board, color: PlayerColor, candidates: Vec<BoardMove>, config: AiConfig) -> mpsc::Receiver<(Option<BoardMove>, SearchStats)> {
+    let board = board.clone();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver may have been dropped if nobody waits for the result any more
+        let _ = sender.send(choose_ai_move(&board, color, &candidates, &config));
+    });
+    receiver
+}
+
+// Counts the move sequences of the given length from this position. Capturing a Product Owner
+// ends the game, so such a move is counted but not followed further.
+fn perft(board: &Board, color: PlayerColor, depth: u32) -> u64 {
+    if depth == 0 { return 1; }
+    let moves = board.legal_moves(color);
+    if depth == 1 { return moves.len() as u64; }
+    moves.iter().map(|mv| perft_after(board, color, mv, depth)).sum()
+}
+
+// Perft count of the subtree below one move, the move itself included in `depth`
+fn perft_after(board: &Board, color: PlayerColor, (from, m): &BoardMove, depth: u32) -> u64 {
+    let mut next = board.clone();
+    match next.apply_move(from.0, from.1, m) {
+        Some(p) if p.piece_type == PieceType::ProductOwner => 1,
+        _ => perft(&next, color.opponent(), depth - 1),
+    }
+}
+
+// Perft count split by root move, sorted by origin then destination square (column first)
+fn perft_divide(board: &Board, color: PlayerColor, depth: u32) -> Vec<(BoardMove, u64)> {
+    let mut moves = board.legal_moves(color);
+    moves.sort_by_key(|((from_r, from_c), m)| (*from_c, *from_r, m.to_c, m.to_r));
+    moves.into_iter().map(|mv| (mv, perft_after(board, color, &mv, depth))).collect()
+}
+
+const MAX_BENCH_DEPTH: u32 = 6;
+
+// Times move generation and perft on the starting position of a board this size, with the
+// default rules, so results can be compared between runs
+fn run_benchmark(width: usize, height: usize, depth: u32, iterations: u32) {
+    let board = Board::new(width, height);
+    outln!("Benchmark: {}x{} starting position, perft depth {}, {} iteration(s)", width, height, depth, iterations);
+
+    let start = Instant::now();
+    let mut generated = 0;
+    for _ in 0..iterations * 1000 {
+        generated += board.all_legal_moves(PlayerColor::White).len();
+    }
+    let elapsed = start.elapsed();
+    outln!("  all_legal_moves: {} calls, {} moves in {:.1} ms ({:.0} calls/s)",
+        iterations * 1000, generated, elapsed.as_secs_f64() * 1000.0, (iterations * 1000) as f64 / elapsed.as_secs_f64());
+
+    let start = Instant::now();
+    let mut nodes = 0;
+    for _ in 0..iterations {
+        nodes = perft(&board, PlayerColor::White, depth);
+    }
+    let elapsed = start.elapsed();
+    outln!("  perft({}): {} nodes, {:.1} ms total ({:.0} nodes/s)",
+        depth, nodes, elapsed.as_secs_f64() * 1000.0, (nodes * iterations as u64) as f64 / elapsed.as_secs_f64());
+}
+
+// A move that was played, kept for history and statistics
+#[derive(Debug, Clone)]
+struct MoveRecord {
+    player: PlayerColor,
+    piece: Piece,
+    from: (usize, usize),
+    to: (usize, usize),
+    captured: Option<Piece>,
+    legal_move_count: usize, // Legal moves the player had to choose from (branching factor)
+    comment: Option<String>, // Annotation added with the comment command
+    decided: bool,           // The capture won the game, marked '#' in notation
+}
+
+impl MoveRecord {
+    // Short notation such as "♖ B1-B4" or "♖ B4xD4" for captures
+    fn notation(&self, board_height: usize) -> String {
+        format!("{} {}", self.piece, self.square_notation(board_height, rank_base()))
+    }
+
+    // The squares alone, e.g. "B4xD4", with '#' after the capture that won the game
+    fn square_notation(&self, board_height: usize, base: usize) -> String {
+        format!("{}{}{}{}",
+            format_square(self.from.0, self.from.1, board_height, base),
+            if self.captured.is_some() { 'x' } else { '-' },
+            format_square(self.to.0, self.to.1, board_height, base),
+            if self.decided { "#" } else { "" })
     }
 }
 
+// Why a game ended, kept with its GameEnded event
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EndReason {
+    OwnerCaptured,
+    PiecesCaptured,       // Without sudden death: the side's last piece was taken
+    CaptureTarget(usize), // The capture target variant
+    NoProgress(usize),    // The no-progress limit, in plies
+    Agreement,
+}
+
+impl EndReason {
+    fn describe(self) -> String {
+        match self {
+            EndReason::OwnerCaptured => "ProductOwner captured".to_string(),
+            EndReason::PiecesCaptured => "no pieces left".to_string(),
+            EndReason::CaptureTarget(target) => format!("{} captures reached", target),
+            EndReason::NoProgress(limit) => format!("{} plies without a capture", limit),
+            EndReason::Agreement => "draw agreed".to_string(),
+        }
+    }
+}
+
+// Something that happened during the game. GameState::events keeps them in order; the game summary
+// and the game-over message are worked out from that log, and the events command prints it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GameEvent {
+    MoveMade { player: PlayerColor, piece: Piece, from: (usize, usize), to: (usize, usize) },
+    CaptureMade { player: PlayerColor, captured: Piece, square: (usize, usize) }, // Follows the MoveMade that took it
+    OwnerAttacked { by: PlayerColor }, // After by's move, by could capture the enemy ProductOwner
+    DrawOffered { by: PlayerColor, accepted: bool },
+    MoveTakenBack { player: PlayerColor }, // The taken-back move's own events are removed from the log
+    GameEnded { result: GameResult, reason: EndReason },
+}
+
+impl GameEvent {
+    // One line of the events command, e.g. "White captured ♞ on C3"
+    fn describe(&self, board_height: usize) -> String {
+        let square = |(r, c): (usize, usize)| coords_to_algebraic(r, c, board_height);
+        match *self {
+            GameEvent::MoveMade { player, piece, from, to } => format!("{:?} moved {} from {} to {}", player, piece, square(from), square(to)),
+            GameEvent::CaptureMade { player, captured, square: at } => format!("{:?} captured {} on {}", player, captured, square(at)),
+            GameEvent::OwnerAttacked { by } => format!("{:?} attacks the {:?} ProductOwner", by, by.opponent()),
+            GameEvent::DrawOffered { by, accepted } => format!("{:?} offered a draw ({})", by, if accepted { "accepted" } else { "declined" }),
+            GameEvent::MoveTakenBack { player } => format!("{:?} took back their move", player),
+            GameEvent::GameEnded { result, reason } => format!("Game over: {} ({})", result.tag(), reason.describe()),
+        }
+    }
+}
+
+// Position key, rules and norepeatpiece square that a MoveCache was filled for
+type MoveCacheKey = (u64, Rules, Option<(usize, usize)>);
+
+// Legal moves already worked out this turn, per square. Only valid for the position, rules and
+// norepeatpiece square in `key`; any other position or rule change empties it.
+#[derive(Default)]
+struct MoveCache {
+    key: Option<MoveCacheKey>,
+    moves: HashMap<(usize, usize), Vec<MoveDetail>>,
+}
+
 struct GameState {
     board: Board,
     current_player: PlayerColor,
@@ -334,6 +1877,34 @@ struct GameState {
     available_moves_for_selected: Option<Vec<MoveDetail>>,
     game_over: bool,
     winner: Option<PlayerColor>,
+    move_history: Vec<MoveRecord>,
+    events: Vec<GameEvent>, // Everything that happened, in order (see GameEvent)
+    summary_shown: bool,
+    mistakes: BTreeMap<&'static str, usize>, // Rejected move attempts per MoveError kind
+    display_options: DisplayOptions,
+    settings: Settings,
+    ai_player: Option<PlayerColor>, // Side played by the computer, if any
+    ai_config: AiConfig,
+    last_search: Option<SearchStats>, // Counters from the computer's most recent move
+    quiet: bool, // Don't print move messages (used while replaying a transcript)
+    move_cache: RefCell<MoveCache>,
+    analysis: bool, // Analysis mode: enables commands that bend the normal flow of play, such as turn
+    edited: bool,   // Changed outside normal play, so replaying the move list no longer reproduces the game
+    start_position: Option<String>, // Placement and side to move the game started from when not the standard setup, e.g. "O5/6/6/6/6/Do1S2 w"
+    white_name: Option<String>,
+    black_name: Option<String>,
+    id: String, // Short identifier written into exports; empty for games rebuilt from a transcript without one
+    rng: Rng,
+    launch_size: (usize, usize), // Board size chosen at startup, for "restart default"
+    peek_next: bool, // Draw the next board once from the opponent's side
+    show_defense: bool, // Draw the next board once with the defense overlay
+    show_coverage: bool, // Draw the next board once with the coverage overlay
+    auto_checked_at: Option<usize>, // Move count when auto-select last ran
+    exit_requested: bool, // Set by the exit command
+    setup_backup: Option<Board>, // The board from before the setup editor was opened; Some while it is open
+    autosave_file: Option<String>, // Where autosave writes the transcript
+    autosave: bool, // Save the transcript to autosave_file after every move
+    takeback_position: Option<Board>, // The board from before the last move, for "takeback" (only its pieces are restored)
 }
 
 impl GameState {
@@ -345,230 +1916,3467 @@ impl GameState {
             available_moves_for_selected: None,
             game_over: false,
             winner: None,
+            move_history: Vec::new(),
+            events: Vec::new(),
+            summary_shown: false,
+            mistakes: BTreeMap::new(),
+            display_options: DisplayOptions::default(),
+            settings: Settings::default(),
+            ai_player: None,
+            ai_config: AiConfig::default(),
+            last_search: None,
+            quiet: false,
+            move_cache: RefCell::new(MoveCache::default()),
+            analysis: false,
+            edited: false,
+            start_position: None,
+            white_name: None,
+            black_name: None,
+            id: String::new(),
+            rng: Rng::new(0),
+            launch_size: (width, height),
+            peek_next: false,
+            show_defense: false,
+            show_coverage: false,
+            auto_checked_at: None,
+            exit_requested: false,
+            setup_backup: None,
+            autosave_file: None,
+            autosave: false,
+            takeback_position: None,
         }
     }
 
-    fn display_turn_info(&self) {
-        if self.game_over {
-            if let Some(winner) = self.winner {
-                println!("{:?} wins! 🎉", winner);
-                println!("Type \"restart\" to play again or \"exit\" to leave.");
-            } else {
-                println!("Game over! It's a draw (somehow?)."); // Should not happen with PO capture rule
-            }
-        } else {
-            println!("Turn: {:?}", self.current_player);
+    // A new game with a fresh identifier drawn from the session's random generator
+    fn start(width: usize, height: usize, mut rng: Rng) -> Self {
+        GameState { id: format!("{:08x}", rng.next_u64() >> 32), rng, ..GameState::new(width, height) }
+    }
+
+    fn result(&self) -> GameResult {
+        match self.winner {
+            _ if !self.game_over => GameResult::InProgress,
+            Some(PlayerColor::White) => GameResult::WhiteWins,
+            Some(PlayerColor::Black) => GameResult::BlackWins,
+            None => GameResult::Draw,
         }
     }
 
-    fn switch_player(&mut self) {
-        self.current_player = self.current_player.opponent();
-        self.selected_square_coords = None;
-        self.available_moves_for_selected = None;
+    // The game's event log, oldest first
+    fn events(&self) -> &[GameEvent] {
+        &self.events
     }
 
-    fn select_piece(&mut self, r: usize, c: usize) -> Result<(), String> {
-        if self.game_over { return Err("The game is over.".to_string()); }
+    // Result and reason from the GameEnded event, if the game has ended
+    fn ending(&self) -> Option<(GameResult, EndReason)> {
+        self.events().iter().rev().find_map(|event| match *event {
+            GameEvent::GameEnded { result, reason } => Some((result, reason)),
+            _ => None,
+        })
+    }
 
-        match self.board.get_piece(r, c) {
-            Some(piece) => {
-                if piece.color == self.current_player {
-                    self.selected_square_coords = Some((r, c));
-                    let moves = self.board.calculate_valid_moves(r, c, piece);
-                    if !moves.is_empty() {
-                        print!("Selected: {} at {}. Available moves: ", piece, coords_to_algebraic(r, c, self.board.height));
-                        for (i, m) in moves.iter().enumerate() {
-                            print!("{}", coords_to_algebraic(m.to_r, m.to_c, self.board.height));
-                            if i < moves.len() - 1 { print!(", "); }
-                        }
-                        println!();
-                    } else {
-                        println!("Selected: {} at {}. No available moves.", piece, coords_to_algebraic(r, c, self.board.height));
-                    }
-                    self.available_moves_for_selected = Some(moves);
-                    Ok(())
-                } else {
-                    Err(format!("Invalid input: You cannot select a {} piece on {:?}'s turn.",
-                        format!("{:?}", piece.color).to_lowercase(), self.current_player))
-                }
-            }
-            None => Err(format!("Invalid input: There is no piece at {}.", coords_to_algebraic(r,c,self.board.height).to_uppercase())),
+    // Color name, followed by the player's name if one was set
+    fn player_label(&self, color: PlayerColor) -> String {
+        let name = match color {
+            PlayerColor::White => &self.white_name,
+            PlayerColor::Black => &self.black_name,
+        };
+        match name {
+            Some(name) => format!("{:?} ({})", color, name),
+            None => format!("{:?}", color),
         }
     }
-    
-    fn attempt_move(&mut self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> Result<(), String> {
-        if self.game_over { return Err("The game is over. Type 'restart' or 'exit'.".to_string()); }
-
-        // Use available_moves_for_selected if a piece was selected, otherwise calculate them now (direct move command)
-        let current_valid_moves = if self.selected_square_coords == Some((from_r, from_c)) {
-            self.available_moves_for_selected.clone().unwrap_or_else(|| {
-                // Should ideally not happen if select was called first, but as a fallback:
-                if let Some(p) = self.board.get_piece(from_r, from_c) {
-                    if p.color == self.current_player {
-                        self.board.calculate_valid_moves(from_r, from_c, p)
-                    } else { vec![] }
-                } else { vec![] }
-            })
-        } else { // If 'move' is called without 'select', or for a different piece
-             if let Some(p) = self.board.get_piece(from_r, from_c) {
-                if p.color == self.current_player {
-                     self.board.calculate_valid_moves(from_r, from_c, p)
-                } else {
-                    // This case is handled by board.move_piece, but good to be explicit
-                    return Err("Invalid move: You can't move your opponent's piece.".to_string());
+
+    fn display_turn_info(&mut self) {
+        if self.game_over {
+            if !self.summary_shown {
+                self.display_game_summary();
+                self.summary_shown = true;
+            }
+            match self.ending() {
+                Some((GameResult::WhiteWins, _)) => outln!("{}", tr("turn.wins", &[("player", &self.player_label(PlayerColor::White))])),
+                Some((GameResult::BlackWins, _)) => outln!("{}", tr("turn.wins", &[("player", &self.player_label(PlayerColor::Black))])),
+                Some((_, EndReason::NoProgress(limit))) => outln!("{}", tr("turn.draw_no_progress", &[("limit", &limit)])),
+                Some((_, EndReason::Agreement)) => outln!("{}", tr("turn.draw_agreed", &[])),
+                _ => {
+                    outln!("{}", tr("turn.draw_unknown", &[])); // Should not happen with PO capture rule
+                    return;
                 }
-            } else {
-                return Err(format!("Invalid move: There is no piece at {}.", coords_to_algebraic(from_r, from_c, self.board.height)));
             }
-        };
-
-
-        match self.board.move_piece(from_r, from_c, to_r, to_c, self.current_player, &current_valid_moves) {
-            Ok(captured_piece_option) => {
-                let moved_piece_symbol = self.board.get_piece(to_r, to_c).map_or('?', |p| format!("{}",p).chars().next().unwrap());
-                print!("Moved {} from {} to {}.", moved_piece_symbol, coords_to_algebraic(from_r, from_c, self.board.height), coords_to_algebraic(to_r, to_c, self.board.height));
-                if let Some(captured) = captured_piece_option {
-                    print!(" Captured {}.", captured);
-                    if captured.piece_type == PieceType::ProductOwner {
-                        self.game_over = true;
-                        self.winner = Some(self.current_player);
-                        // Game over message will be handled by display_turn_info
+            outln!("{}", tr("turn.restart_or_exit", &[]));
+        } else if self.setup_backup.is_some() {
+            outln!("Editing the position. {}", SETUP_USAGE);
+        } else {
+            let key = if self.analysis { "turn.current_analysis" } else { "turn.current" };
+            outln!("{}", tr(key, &[("player", &self.player_label(self.current_player))]));
+            if self.board.rules.play_on {
+                for color in [PlayerColor::White, PlayerColor::Black] {
+                    if self.board.find_owner(color).is_none() {
+                        outln!("{}", tr("turn.owner_gone", &[("player", &self.player_label(color))]));
                     }
                 }
-                println!();
-                
-                if !self.game_over {
-                    self.switch_player();
-                }
-                Ok(())
             }
-            Err(e) => Err(e),
+            if let Some(target) = self.board.rules.capture_target {
+                outln!("{}", tr("turn.captures", &[("white", &self.captures_by(PlayerColor::White)),
+                    ("black", &self.captures_by(PlayerColor::Black)), ("target", &target)]));
+            }
+            if let Some(limit) = self.board.rules.no_progress_limit.filter(|_| self.settings.show_progress) {
+                outln!("{}", tr("turn.no_progress", &[("plies", &self.plies_without_capture()), ("limit", &limit)]));
+            }
+            if self.only_owner_can_move() {
+                outln!("{}", tr("turn.only_owner", &[]));
+            }
+            if self.settings.win_alert && self.ai_player != Some(self.current_player) && !self.board.owner_captures(self.current_player).is_empty() {
+                outln!("{}", tr("turn.win_alert", &[]));
+            }
         }
     }
+
+    // Whose view the board is drawn from under fog of war: the human in a game against the
+    // computer, otherwise the player to move. None when the fog is off or the game is over
+    fn fog_viewer(&self) -> Option<PlayerColor> {
+        if !self.board.rules.fog_of_war || self.game_over || self.setup_backup.is_some() { return None; }
+        Some(self.ai_player.map_or(self.current_player, |ai| ai.opponent()))
+    }
+
+    // Fog of war hides what stands on unseen squares, so commands that name one are refused
+    // rather than answering "no piece" or "enemy piece"
+    fn check_visible(&self, r: usize, c: usize) -> Result<(), MoveError> {
+        match self.fog_viewer() {
+            Some(viewer) if !self.board.visible_squares(viewer)[r][c] =>
+                Err(MoveError::Hidden(coords_to_algebraic(r, c, self.board.height))),
+            _ => Ok(()),
+        }
+    }
+
+    // True when the current player has moves but all of them are ProductOwner moves
+    fn only_owner_can_move(&self) -> bool {
+        let moves = self.current_legal_moves();
+        !moves.is_empty() && moves.iter().all(|((r, c), _)| {
+            self.board.get_piece(*r, *c).is_some_and(|p| p.piece_type == PieceType::ProductOwner)
+        })
+    }
+
+    // The ProductOwner move that leaves it attacked by the fewest enemy pieces, with that count
+    fn safest_owner_move(&self) -> Option<(BoardMove, usize)> {
+        let enemy = self.current_player.opponent();
+        self.current_legal_moves().into_iter()
+            .filter(|((r, c), _)| self.board.get_piece(*r, *c).is_some_and(|p| p.piece_type == PieceType::ProductOwner))
+            .map(|(from, m)| {
+                let mut next = self.board.clone();
+                next.apply_move(from.0, from.1, &m);
+                ((from, m), next.attackers(m.to_r, m.to_c, enemy))
+            })
+            .min_by_key(|(_, attackers)| *attackers)
+    }
+
+    // Lists each move of the piece on (r, c) with how many of our pieces the opponent could capture
+    // afterwards, safest first
+    fn display_tree(&self, r: usize, c: usize) -> Result<(), String> {
+        self.check_visible(r, c).map_err(|e| e.to_string())?;
+        let height = self.board.height;
+        let square = coords_to_algebraic(r, c, height);
+        let piece = match self.board.get_piece(r, c) {
+            Some(p) if p.color == self.current_player => p,
+            Some(_) => return Err(format!("Invalid input: {} holds an opponent's piece; 'tree' explores your own moves.", square)),
+            None => return Err(format!("Invalid input: There is no piece at {}.", square)),
+        };
+        let moves = self.legal_moves_from(r, c, piece);
+        if moves.is_empty() {
+            outln!("{} at {} has no legal moves.", piece, square);
+            return Ok(());
+        }
+
+        let enemy = self.current_player.opponent();
+        let mut branches: Vec<(String, Vec<String>, bool)> = moves.iter().map(|m| {
+            let mut next = self.board.clone();
+            let captured = next.apply_move(r, c, m);
+            let wins = captured.is_some_and(|p| p.piece_type == PieceType::ProductOwner) && !self.board.rules.play_on;
+            let attacked = (0..next.height).flat_map(|ar| (0..next.width).map(move |ac| (ar, ac)))
+                .filter(|&(ar, ac)| next.grid[ar][ac].is_some_and(|p| p.color == self.current_player) && next.attackers(ar, ac, enemy) > 0)
+                .map(|(ar, ac)| coords_to_algebraic(ar, ac, height))
+                .collect();
+            let notation = format!("{}{}{}", square, if captured.is_some() { 'x' } else { '-' }, coords_to_algebraic(m.to_r, m.to_c, height));
+            (notation, if wins { Vec::new() } else { attacked }, wins)
+        }).collect();
+        branches.sort_by_key(|(_, attacked, wins)| (!wins, attacked.len()));
+
+        outln!("Moves for {} at {}, safest first (our pieces the opponent could capture next):", piece, square);
+        for (notation, attacked, wins) in branches {
+            if wins { outln!("  {:<8} wins the game", notation); }
+            else if attacked.is_empty() { outln!("  {:<8} 0", notation); }
+            else { outln!("  {:<8} {} ({})", notation, attacked.len(), attacked.join(", ")); }
+        }
+        Ok(())
+    }
+
+    // The computer's evaluation of the position for the player to move, term by term
+    fn display_eval(&self) {
+        let terms = eval_terms(&self.board, self.current_player);
+        let weights = self.ai_config.weights;
+        outln!("Evaluation for {} (positive is good for {}):", self.player_label(self.current_player), self.player_label(self.current_player));
+        for (name, value, weight) in [("material", terms.material, weights.material), ("mobility", terms.mobility, weights.mobility),
+                                      ("center", terms.center, weights.center), ("owner safety", terms.owner_safety, weights.owner_safety)] {
+            outln!("  {:<14}{:>+5} x {:<3} = {:>+6}", name, value, weight, value * weight);
+        }
+        outln!("  {:<14}{:>21}", "total", format!("{:+}", terms.weighted(&weights)));
+    }
+
+    // Suggests a move: the safest escape when only the ProductOwner can move, otherwise a short AI search
+    fn display_hint(&self) {
+        if self.game_over { outln!("The game is over."); return; }
+        let height = self.board.height;
+        let describe = |(from, m): BoardMove| format!("move {} {}", coords_to_algebraic(from.0, from.1, height), coords_to_algebraic(m.to_r, m.to_c, height));
+
+        if self.only_owner_can_move() {
+            if let Some((mv, attackers)) = self.safest_owner_move() {
+                outln!("Hint: only your ProductOwner can move. Safest: {} (attacked by {} enemy piece(s) there)", describe(mv), attackers);
+            }
+            return;
+        }
+        let config = AiConfig { time_budget_ms: 500, ..self.ai_config };
+        match choose_ai_move(&self.board, self.current_player, &self.current_legal_moves(), &config).0 {
+            Some(mv) => outln!("Hint: {}", describe(mv)),
+            None => outln!("{:?} has no legal moves.", self.current_player),
+        }
+    }
+
+    // Number of enemy pieces the given side has captured so far
+    fn captures_by(&self, color: PlayerColor) -> usize {
+        self.events().iter().filter(|event| matches!(event, GameEvent::CaptureMade { player, .. } if *player == color)).count()
+    }
+
+    // Plies played since the last capture, counted by the no-progress rule
+    fn plies_without_capture(&self) -> usize {
+        self.move_history.iter().rev().take_while(|m| m.captured.is_none()).count()
+    }
+
+    fn display_progress(&self) {
+        match self.board.rules.no_progress_limit {
+            Some(limit) => {
+                let plies = self.plies_without_capture();
+                outln!("{} plies without a capture; the game is drawn after {} more (limit {}).", plies, limit.saturating_sub(plies), limit);
+            }
+            None => outln!("No-progress rule: off (type \"noprogress <n>\" to draw after n plies without a capture)"),
+        }
+    }
+
+    fn display_branching(&self) {
+        let current = self.current_legal_moves().len();
+        outln!("{:?} has {} legal moves.", self.current_player, current);
+        if self.move_history.is_empty() {
+            outln!("Average branching factor: n/a (no moves played yet)");
+        } else {
+            let total: usize = self.move_history.iter().map(|m| m.legal_move_count).sum();
+            outln!("Average branching factor: {:.2} over {} moves", total as f64 / self.move_history.len() as f64, self.move_history.len());
+        }
+    }
+
+    // The current side's longest legal move, measured in king steps (an L-move counts as 2), and the
+    // farthest each piece type can go right now
+    fn display_longest_move(&self) {
+        let distance = |((from_r, from_c), m): &BoardMove| from_r.abs_diff(m.to_r).max(from_c.abs_diff(m.to_c));
+        let moves = self.current_legal_moves();
+        // Searched from the back so ties go to the first move in board order
+        let Some(longest) = moves.iter().rev().max_by_key(|mv| distance(mv)) else {
+            outln!("{:?} has no legal moves.", self.current_player);
+            return;
+        };
+        let ((from_r, from_c), m) = longest;
+        let piece = self.board.grid[*from_r][*from_c].map_or("?".to_string(), |p| p.to_string());
+        outln!("Longest move: {} {}{}{} ({} squares)", piece, coords_to_algebraic(*from_r, *from_c, self.board.height),
+            if m.is_capture { 'x' } else { '-' }, coords_to_algebraic(m.to_r, m.to_c, self.board.height), distance(longest));
+        let reach: Vec<String> = [PieceType::ProductOwner, PieceType::Designer, PieceType::Developer].iter().map(|&piece_type| {
+            let farthest = moves.iter()
+                .filter(|((r, c), _)| self.board.grid[*r][*c].is_some_and(|p| p.piece_type == piece_type))
+                .map(distance)
+                .max();
+            format!("{:?} {}", piece_type, farthest.map_or("-".to_string(), |d| d.to_string()))
+        }).collect();
+        outln!("Reach: {}", reach.join(", "));
+    }
+
+    // The current player's pieces that are fully blocked in, with no move even before rules such as
+    // mustcapture or norepeatpiece are applied
+    fn display_stuck_pieces(&self) {
+        let height = self.board.height;
+        let stuck: Vec<String> = (0..height).flat_map(|r| (0..self.board.width).map(move |c| (r, c)))
+            .filter_map(|(r, c)| self.board.grid[r][c].filter(|p| p.color == self.current_player).map(|p| (r, c, p)))
+            .filter(|&(r, c, piece)| self.board.calculate_valid_moves(r, c, piece).is_empty())
+            .map(|(r, c, piece)| format!("{} {}", piece, coords_to_algebraic(r, c, height)))
+            .collect();
+        if stuck.is_empty() {
+            outln!("None of {}'s pieces are stuck.", self.player_label(self.current_player));
+        } else {
+            outln!("Stuck pieces (no legal moves): {}", stuck.join(", "));
+        }
+    }
+
+    // One-time statistics printed when the game ends
+    fn display_game_summary(&self) {
+        // A capture follows its move in the log, so a streak only counts once the next move shows it went on
+        let mut longest_quiet_streak = 0;
+        let mut streak = 0;
+        let mut moves = 0;
+        for event in self.events() {
+            match event {
+                GameEvent::MoveMade { .. } => {
+                    longest_quiet_streak = longest_quiet_streak.max(streak);
+                    streak += 1;
+                    moves += 1;
+                }
+                GameEvent::CaptureMade { .. } => streak = 0,
+                _ => {}
+            }
+        }
+        longest_quiet_streak = longest_quiet_streak.max(streak);
+
+        outln!("=== Game summary ===");
+        outln!("Total moves: {}", moves);
+        outln!("Captures: White {}, Black {}", self.captures_by(PlayerColor::White), self.captures_by(PlayerColor::Black));
+        outln!("Longest streak without a capture: {} moves", longest_quiet_streak);
+        if let Some(last) = self.move_history.last().filter(|_| self.winner.is_some()) {
+            outln!("Decisive move: {} by {}", last.notation(self.board.height), self.player_label(last.player));
+        }
+        if let Some(winner) = self.winner.filter(|_| self.settings.tournament_scoring) {
+            outln!("Tournament score: {} {}, {} 0", self.player_label(winner), tournament_score(&self.board, winner), self.player_label(winner.opponent()));
+        }
+        outln!();
+    }
+
+    fn display_score(&self) {
+        match self.winner {
+            Some(winner) => outln!("Tournament score: {} {}, {} 0 ({} win points + {} x {} material)",
+                self.player_label(winner), tournament_score(&self.board, winner), self.player_label(winner.opponent()),
+                TOURNAMENT_WIN_POINTS, TOURNAMENT_MATERIAL_WEIGHT, surviving_material(&self.board, winner)),
+            None => {
+                outln!("No winner yet. A win now would score:");
+                for color in [PlayerColor::White, PlayerColor::Black] {
+                    outln!("  {}: {}", self.player_label(color), tournament_score(&self.board, color));
+                }
+            }
+        }
+    }
+
+    fn switch_player(&mut self) {
+        self.current_player = self.current_player.opponent();
+        self.selected_square_coords = None;
+        self.available_moves_for_selected = None;
+    }
+
+    // Lets the computer pick and play a move for the current player. Returns false if it couldn't move.
+    fn play_ai_turn(&mut self) -> bool {
+        out!("AI is thinking");
+        io::stdout().flush().unwrap();
+        // The search runs in the background while this thread prints a dot every quarter second
+        let receiver = spawn_search(&self.board, self.current_player, self.current_legal_moves(), self.ai_config);
+        let (choice, stats) = loop {
+            match receiver.recv_timeout(Duration::from_millis(250)) {
+                Ok(result) => break result,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    out!(".");
+                    io::stdout().flush().unwrap();
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break (None, SearchStats::default()),
+            }
+        };
+        self.last_search = Some(stats);
+        outln!();
+
+        match choice {
+            Some((from, m)) => match self.attempt_move(from.0, from.1, m.to_r, m.to_c) {
+                Ok(()) => {
+                    if let Some(reason) = stats.reason.filter(|_| self.settings.verbose) { outln!("AI reasoning: {}", reason); }
+                    true
+                }
+                Err(e) => { outln!("{}", e); false }
+            },
+            None => { outln!("{:?} has no legal moves.", self.current_player); false }
+        }
+    }
+
+    // Plays `games` computer-vs-computer games between configs A and B on this board size and rules, and
+    // prints the match score. Games come in pairs that share a random opening (from the session seed)
+    // with colors swapped; the search has no time limit, so the same seed always gives the same match.
+    fn run_engine_match(&mut self, games: usize, a: AiConfig, b: AiConfig) {
+        let (width, height, rules) = (self.board.width, self.board.height, self.board.rules);
+        let mut opening = Vec::new();
+        let (mut a_wins, mut b_wins, mut draws, mut total_plies) = (0, 0, 0, 0);
+        for game in 0..games {
+            let a_color = if game % 2 == 0 { PlayerColor::White } else { PlayerColor::Black };
+            let mut state = GameState { board: Board::with_rules(width, height, rules), quiet: true, ..GameState::new(width, height) };
+            if game % 2 == 0 { opening.clear(); }
+            for ply in 0..MATCH_OPENING_PLIES {
+                let moves = state.current_legal_moves();
+                if moves.is_empty() { break; }
+                if ply == opening.len() { opening.push(moves[self.rng.below(moves.len())]); }
+                let (from, m) = opening[ply];
+                let _ = state.attempt_move(from.0, from.1, m.to_r, m.to_c);
+            }
+            while !state.game_over && state.move_history.len() < MAX_MATCH_PLIES {
+                let config = AiConfig { time_budget_ms: 3_600_000, ..if state.current_player == a_color { a } else { b } };
+                let (choice, _) = choose_ai_move(&state.board, state.current_player, &state.current_legal_moves(), &config);
+                let Some((from, m)) = choice else { break; };
+                if state.attempt_move(from.0, from.1, m.to_r, m.to_c).is_err() { break; }
+            }
+            let plies = state.move_history.len();
+            total_plies += plies;
+            let result = match state.winner {
+                Some(winner) if winner == a_color => { a_wins += 1; "A wins" }
+                Some(_) => { b_wins += 1; "B wins" }
+                None => { draws += 1; "draw" }
+            };
+            outln!("Game {}: A plays {:?}, {} in {} plies", game + 1, a_color, result, plies);
+        }
+        outln!("Match over {} games: A {} wins, B {} wins, {} draws (score A {} - B {})", games, a_wins, b_wins, draws,
+            a_wins as f64 + draws as f64 / 2.0, b_wins as f64 + draws as f64 / 2.0);
+        outln!("Average game length: {:.1} plies", total_plies as f64 / games.max(1) as f64);
+    }
+
+    // Selects the piece if it is the only one with a legal move this turn (e.g. a single forced capture)
+    fn auto_select_forced_piece(&mut self) {
+        let moves = self.current_legal_moves();
+        let first = match moves.first() {
+            Some((square, _)) => *square,
+            None => return,
+        };
+        if moves.iter().all(|(square, _)| *square == first) {
+            outln!("Only one piece can move this turn.");
+            select_and_report(self, first.0, first.1);
+        }
+    }
+
+    // Checks the position's invariants and returns a description of each violation
+    fn validate(&self) -> Vec<String> {
+        let board = &self.board;
+        let mut problems = board.grid_problems();
+
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            // The side that lost its ProductOwner legitimately has none left
+            let expected = if self.game_over && self.winner == Some(color.opponent()) { 0 } else { 1 };
+            let count = board.count_pieces(color, PieceType::ProductOwner);
+            // Without sudden death a captured ProductOwner simply stays off the board
+            if count != expected && !(board.rules.play_on && count == 0) {
+                problems.push(format!("{:?} has {} ProductOwners (expected {}).", color, count, expected));
+            }
+        }
+
+        if let Some((r, c)) = self.selected_square_coords {
+            match board.get_piece(r, c) {
+                Some(piece) if piece.color == self.current_player => {
+                    if self.available_moves_for_selected.as_ref().is_some_and(|cached| *cached != self.compute_legal_moves_from(r, c, piece)) {
+                        problems.push(format!("Highlighted moves for the selected piece on {} are out of date.", coords_to_algebraic(r, c, board.height)));
+                    }
+                }
+                Some(piece) => problems.push(format!("Selected square {} holds a {:?} piece on {:?}'s turn.",
+                    coords_to_algebraic(r, c, board.height), piece.color, self.current_player)),
+                None => problems.push(format!("Selected square {} is empty or off the board.", coords_to_algebraic(r, c, board.height))),
+            }
+        }
+        problems
+    }
+
+    // Per-direction breakdown of how far a Developer can go and why it stops
+    fn display_reach(&self, r: usize, c: usize) -> Result<(), String> {
+        self.check_visible(r, c).map_err(|e| e.to_string())?;
+        let height = self.board.height;
+        let piece = match self.board.get_piece(r, c) {
+            Some(p) => p,
+            None => return Err(format!("Invalid input: There is no piece at {}.", coords_to_algebraic(r, c, height))),
+        };
+        if piece.piece_type != PieceType::Developer {
+            return Err(format!("Invalid input: 'reach' only explains Developers; {} at {} is a {:?}.",
+                piece, coords_to_algebraic(r, c, height), piece.piece_type));
+        }
+
+        outln!("Reach of {} at {}:", piece, coords_to_algebraic(r, c, height));
+        for (dr, dc) in DIRECTIONS.iter() {
+            if !self.board.developer_direction_allowed(*dr, *dc) {
+                outln!("  {:<2}: no moves (diagonals are off under dev_orthogonal)", direction_name(*dr, *dc));
+                continue;
+            }
+            let mut furthest: Option<(isize, MoveDetail)> = None;
+            let mut stops = Vec::new();
+            for dist in 1..=3 {
+                let square = self.board.offset_square(r, c, dr * dist, dc * dist)
+                    .map(|(to_r, to_c)| coords_to_algebraic(to_r, to_c, height))
+                    .unwrap_or_default();
+                match self.board.developer_step(r, c, piece, *dr, *dc, dist) {
+                    DeveloperStep::Land(m) => { furthest = Some((dist, m)); stops.clear(); }
+                    DeveloperStep::OffBoard => { stops.push("edge of the board".to_string()); break; }
+                    DeveloperStep::Occupied => stops.push(format!("{} occupied", square)),
+                    DeveloperStep::FriendlyOnPath => stops.push(format!("{} has a friendly piece on the path", square)),
+                    DeveloperStep::TwoEnemiesOnPath => stops.push(format!("{} has two enemies on the path", square)),
+                }
+            }
+
+            let mut line = match furthest {
+                Some((dist, m)) => {
+                    let mut text = format!("{} square{} to {}", dist, if dist == 1 { "" } else { "s" }, coords_to_algebraic(m.to_r, m.to_c, height));
+                    if m.is_capture {
+                        let (cap_r, cap_c) = m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c));
+                        if let Some(captured) = self.board.get_piece(cap_r, cap_c) {
+                            text.push_str(&format!(", capturing {} on {}", captured, coords_to_algebraic(cap_r, cap_c, height)));
+                        }
+                    }
+                    text
+                }
+                None => "no moves".to_string(),
+            };
+            if !stops.is_empty() {
+                line.push_str(&format!(" (stops: {})", stops.join(", ")));
+            }
+            outln!("  {:<2}: {}", direction_name(*dr, *dc), line);
+        }
+        Ok(())
+    }
+
+    // Moves a piece may make this turn once game rules such as mustcapture are applied. Results
+    // are cached until the position or rules change, since select and move both ask for them.
+    fn legal_moves_from(&self, r: usize, c: usize, piece: Piece) -> Vec<MoveDetail> {
+        let key = (self.board.position_key(self.current_player), self.board.rules, self.repeat_blocked_square());
+        let mut cache = self.move_cache.borrow_mut();
+        if cache.key != Some(key) {
+            cache.key = Some(key);
+            cache.moves.clear();
+        }
+        cache.moves.entry((r, c)).or_insert_with(|| self.compute_legal_moves_from(r, c, piece)).clone()
+    }
+
+    fn compute_legal_moves_from(&self, r: usize, c: usize, piece: Piece) -> Vec<MoveDetail> {
+        if self.repeat_blocked_square() == Some((r, c)) { return Vec::new(); }
+        let mut moves = self.board.calculate_valid_moves(r, c, piece);
+        if self.board.rules.must_capture && !self.board.capturing_moves(piece.color).is_empty() {
+            moves.retain(|m| m.is_capture);
+        }
+        if self.strict_applies() {
+            moves.retain(|m| !self.board.exposes_owner(r, c, m, piece.color));
+        }
+        moves
+    }
+
+    // Every move the current player may make this turn, as ((from_r, from_c), move)
+    fn current_legal_moves(&self) -> Vec<BoardMove> {
+        let blocked = self.repeat_blocked_square();
+        let strict = self.strict_applies();
+        self.board.legal_moves(self.current_player).into_iter()
+            .filter(|((r, c), m)| Some((*r, *c)) != blocked && !(strict && self.board.exposes_owner(*r, *c, m, self.current_player)))
+            .collect()
+    }
+
+    // Whether strict removes any moves this turn: only when the current player has a move that keeps
+    // their ProductOwner safe, so a side is never left without a move.
+    fn strict_applies(&self) -> bool {
+        self.board.rules.strict && self.board.legal_moves(self.current_player).iter()
+            .any(|((r, c), m)| !self.board.exposes_owner(*r, *c, m, self.current_player))
+    }
+
+    // Under norepeatpiece, the square of the piece the current player moved last turn, which may not
+    // move now. The rule is dropped (None) when that piece has every legal move, e.g. a lone
+    // ProductOwner or the only capture under mustcapture, so a side is never left without a move.
+    fn repeat_blocked_square(&self) -> Option<(usize, usize)> {
+        if !self.board.rules.no_repeat_piece { return None; }
+        let last = self.move_history.iter().rev().find(|record| record.player == self.current_player)?.to;
+        Some(last).filter(|square| self.board.legal_moves(self.current_player).iter().any(|(from, _)| from != square))
+    }
+
+    // "Fork!" line naming the enemy pieces the piece on (r, c) could capture next turn, when there are two or more
+    fn fork_message(&self, r: usize, c: usize) -> Option<String> {
+        let piece = self.board.get_piece(r, c)?;
+        let mut targets: Vec<(usize, usize)> = self.board.calculate_valid_moves(r, c, piece).iter()
+            .filter(|m| m.is_capture)
+            .map(|m| m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c)))
+            .collect();
+        targets.sort();
+        targets.dedup();
+        if targets.len() < 2 { return None; }
+        let attacked: Vec<String> = targets.iter()
+            .filter_map(|&(tr, tc)| self.board.get_piece(tr, tc).map(|p| format!("{} on {}", p, coords_to_algebraic(tr, tc, self.board.height))))
+            .collect();
+        Some(format!("Fork! {} on {} attacks {}.", piece, coords_to_algebraic(r, c, self.board.height), attacked.join(", ")))
+    }
+
+    // Selects the current player's piece on (r, c) and returns its legal moves (printing is left to the caller)
+    fn select_piece(&mut self, r: usize, c: usize) -> Result<Vec<MoveDetail>, MoveError> {
+        if self.game_over { return Err(MoveError::GameOver); }
+        self.check_visible(r, c)?;
+
+        match self.board.get_piece(r, c) {
+            Some(piece) if piece.color == self.current_player => {
+                let moves = self.legal_moves_from(r, c, piece);
+                self.selected_square_coords = Some((r, c));
+                self.available_moves_for_selected = Some(moves.clone());
+                Ok(moves)
+            }
+            Some(_) => Err(MoveError::WrongColor),
+            None => Err(MoveError::NoPiece(coords_to_algebraic(r, c, self.board.height))),
+        }
+    }
+    
+    // Plays a move for the current player, tallying the reason if it is rejected
+    fn attempt_move(&mut self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> Result<(), MoveError> {
+        let result = self.try_move(from_r, from_c, to_r, to_c);
+        match &result {
+            Ok(()) => self.write_autosave(),
+            Err(e) => *self.mistakes.entry(e.kind()).or_insert(0) += 1,
+        }
+        result
+    }
+
+    // Reverts the last move, putting back the board from before it and handing the turn back to its player
+    fn take_back(&mut self) {
+        let Some(board) = self.takeback_position.take() else { return; };
+        let Some(last) = self.move_history.pop() else { return; };
+        let made_at = self.events.iter().rposition(|event| matches!(event, GameEvent::MoveMade { .. })).unwrap_or(self.events.len());
+        self.events.truncate(made_at);
+        self.events.push(GameEvent::MoveTakenBack { player: last.player });
+        // Only the pieces go back; rules toggled since the move stay as they are
+        self.board.grid = board.grid;
+        self.board.hash = board.hash;
+        self.current_player = last.player;
+        self.selected_square_coords = None;
+        self.available_moves_for_selected = None;
+        self.auto_checked_at = None;
+        self.write_autosave();
+    }
+
+    // Saves the transcript to the autosave file, if autosave is on. It is written to a temporary file
+    // first and renamed over the old save, so a crash mid-write leaves the previous save intact.
+    // Games edited mid-game can't be rebuilt from their moves, so they aren't saved (see the turn command).
+    fn write_autosave(&self) {
+        let Some(file) = self.autosave_file.as_ref().filter(|_| self.autosave && !self.edited) else { return; };
+        let temp = format!("{}.tmp", file);
+        if let Err(e) = std::fs::write(&temp, self.to_transcript()).and_then(|()| std::fs::rename(&temp, file)) {
+            outln!("Autosave to {} failed: {}", file, e);
+        }
+    }
+
+    // The game as text: PGN-style tags followed by numbered moves with any comments in braces,
+    // e.g. "1. B1-B4 {Developer out early} F6xB6"
+    fn to_transcript(&self) -> String {
+        let mut transcript = if self.id.is_empty() { String::new() } else { format!("[Id \"{}\"]\n", self.id) };
+        transcript.push_str(&format!("[Size \"{}x{}\"]\n", self.board.width, self.board.height));
+        transcript.push_str(&format!("[Rules \"{}\"]\n", self.board.rules.to_tag()));
+        if let Some(start) = &self.start_position { transcript.push_str(&format!("[Position \"{}\"]\n", start)); }
+        for (tag, name) in [("White", &self.white_name), ("Black", &self.black_name)] {
+            if let Some(name) = name { transcript.push_str(&format!("[{} \"{}\"]\n", tag, name.replace('"', "'"))); }
+        }
+        let result = self.result().tag();
+        transcript.push_str(&format!("[Result \"{}\"]\n\n", result));
+
+        let mut moves = Vec::new();
+        for (i, record) in self.move_history.iter().enumerate() {
+            if i % 2 == 0 { moves.push(format!("{}.", i / 2 + 1)); }
+            moves.push(record.square_notation(self.board.height, 1));
+            if let Some(comment) = &record.comment { moves.push(format!("{{{}}}", comment)); }
+        }
+        moves.push(result.to_string());
+        transcript.push_str(&moves.join(" "));
+        transcript.push('\n');
+        transcript
+    }
+
+    // The board the game started from: the standard setup, or the edited one it was started from
+    fn starting_board(&self) -> Board {
+        self.start_position.as_deref().and_then(|start| starting_position(start, self.board.rules).ok()).map(|(board, _)| board)
+            .unwrap_or_else(|| Board::with_rules(self.board.width, self.board.height, self.board.rules))
+    }
+
+    // Rebuilds a game from to_transcript's output by replaying its moves from the start
+    fn from_transcript(transcript: &str) -> Result<GameState, String> {
+        let mut tags = BTreeMap::new();
+        let mut move_text = String::new();
+        for line in transcript.lines().map(str::trim) {
+            if let Some(tag) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let (name, value) = tag.split_once(' ').ok_or(format!("Malformed tag: {}", line))?;
+                tags.insert(name, value.trim().trim_matches('"'));
+            } else {
+                move_text.push_str(line);
+                move_text.push(' ');
+            }
+        }
+
+        let size = tags.get("Size").ok_or("The transcript has no Size tag.")?;
+        let (width, height) = size.split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+            .filter(|(w, h)| (MIN_DIM..=MAX_DIM).contains(w) && (MIN_DIM..=MAX_DIM).contains(h))
+            .ok_or(format!("Invalid board size: {}", size))?;
+
+        let mut state = GameState::new(width, height);
+        if let Some(tag) = tags.get("Rules") {
+            state.board = Board::with_rules(width, height, Rules::from_tag(tag)?);
+        }
+        if let Some(start) = tags.get("Position") {
+            let (board, to_move) = starting_position(start, state.board.rules)?;
+            if (board.width, board.height) != (width, height) { return Err(format!("The Position tag doesn't fit the {} board.", size)); }
+            state.board = board;
+            state.current_player = to_move;
+            state.start_position = Some(start.to_string());
+        }
+        state.white_name = tags.get("White").map(|s| s.to_string());
+        state.black_name = tags.get("Black").map(|s| s.to_string());
+        state.id = tags.get("Id").map_or(String::new(), |s| s.to_string());
+
+        state.quiet = true;
+        let mut rest = move_text.as_str();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() { break; }
+            // A {comment} belongs to the move before it
+            if let Some(after_brace) = rest.strip_prefix('{') {
+                let end = after_brace.find('}').ok_or("The transcript has an unclosed comment.")?;
+                if let Some(record) = state.move_history.last_mut() { record.comment = Some(after_brace[..end].trim().to_string()); }
+                rest = &after_brace[end + 1..];
+                continue;
+            }
+            let end = rest.find(|ch: char| ch.is_whitespace() || ch == '{').unwrap_or(rest.len());
+            let token = &rest[..end];
+            rest = &rest[end..];
+
+            if token.ends_with('.') || ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) { continue; } // Move numbers and result
+            let (from, to, _) = split_move_token(token.trim_end_matches('#')).ok_or(format!("Invalid move: {}", token))?;
+            let (from_r, from_c) = parse_square(from, height, width, 1)?;
+            let (to_r, to_c) = parse_square(to, height, width, 1)?;
+            state.attempt_move(from_r, from_c, to_r, to_c).map_err(|e| format!("Move {} failed: {}", token, e))?;
+        }
+        state.quiet = false;
+        Ok(state)
+    }
+
+    // True if the current player's move would leave their ProductOwner open to capture.
+    // Illegal moves and moves that win the game outright are never reported.
+    fn leaves_owner_hanging(&self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> bool {
+        let m = match self.board.get_piece(from_r, from_c).filter(|p| p.color == self.current_player)
+            .and_then(|p| self.legal_moves_from(from_r, from_c, p).into_iter().find(|m| m.to_r == to_r && m.to_c == to_c)) {
+            Some(m) => m,
+            None => return false,
+        };
+        self.board.exposes_owner(from_r, from_c, &m, self.current_player)
+    }
+
+    // True if (from -> to) is a capture made while the current player's ProductOwner is already attacked
+    fn is_capture_under_threat(&self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> bool {
+        matches!(self.board.classify_move((from_r, from_c), (to_r, to_c)), Some(MoveClass::LandingCapture | MoveClass::JumpCapture(_)))
+            && self.board.find_owner(self.current_player)
+                .is_some_and(|(r, c)| self.board.attackers(r, c, self.current_player.opponent()) > 0)
+    }
+
+    // The warnhang question for a move that leaves our ProductOwner capturable, or None if it doesn't.
+    // A capture made while the ProductOwner is already attacked gets its own wording, since players
+    // tend to assume that taking a piece deals with the threat.
+    fn hanging_warning(&self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> Option<String> {
+        if !self.leaves_owner_hanging(from_r, from_c, to_r, to_c) { return None; }
+        Some(if self.is_capture_under_threat(from_r, from_c, to_r, to_c) {
+            tr("warnhang.capture_exposes_owner", &[])
+        } else {
+            tr("warnhang.exposes_owner", &[])
+        })
+    }
+
+    // Board marks for the defense overlay: (x) for the current player's defended pieces, !x! for undefended ones
+    fn defense_marks(&self) -> BTreeMap<(usize, usize), (char, char)> {
+        let mut marks = BTreeMap::new();
+        for r in 0..self.board.height {
+            for c in 0..self.board.width {
+                if self.board.grid[r][c].is_some_and(|p| p.color == self.current_player) {
+                    marks.insert((r, c), if self.board.defenders(r, c) > 0 { ('(', ')') } else { ('!', '!') });
+                }
+            }
+        }
+        marks
+    }
+
+    // Draws the board with each empty square showing how many of `color`'s pieces can move to it (9 for
+    // nine or more). Under fog of war only the viewer's own heatmap is available.
+    fn display_heatmap(&self, color: PlayerColor) {
+        let viewer = self.fog_viewer();
+        if viewer.is_some_and(|viewer| viewer != color) {
+            outln!("Invalid input: {:?}'s heatmap is hidden by the fog.", color);
+            return;
+        }
+        let mut reach: BTreeMap<(usize, usize), Vec<(usize, usize)>> = BTreeMap::new();
+        for (from, m) in self.board.all_legal_moves(color) {
+            let pieces = reach.entry((m.to_r, m.to_c)).or_default();
+            if !pieces.contains(&from) { pieces.push(from); }
+        }
+        let labels: BTreeMap<(usize, usize), char> = reach.iter()
+            .filter(|((r, c), _)| self.board.grid[*r][*c].is_none())
+            .map(|(square, pieces)| (*square, char::from_digit(pieces.len().min(9) as u32, 10).unwrap_or('9')))
+            .collect();
+        out!("{}", self.board.render_labelled(None, &None, false, &self.display_options, &BTreeMap::new(), viewer, &labels));
+        let empty = self.board.grid.iter().flatten().filter(|square| square.is_none()).count();
+        outln!("Heatmap: how many {:?} pieces can move to each empty square ({} of {} reachable)", color, labels.len(), empty);
+    }
+
+    // Board marks for the coverage overlay: ( ) on empty squares one of the current player's pieces
+    // could move to quietly, { } where two or more could
+    fn coverage_marks(&self) -> BTreeMap<(usize, usize), (char, char)> {
+        let mut reach: BTreeMap<(usize, usize), Vec<(usize, usize)>> = BTreeMap::new();
+        for (from, m) in self.board.all_legal_moves(self.current_player) {
+            if m.is_capture { continue; }
+            let pieces = reach.entry((m.to_r, m.to_c)).or_default();
+            if !pieces.contains(&from) { pieces.push(from); }
+        }
+        reach.into_iter().map(|(square, pieces)| (square, if pieces.len() > 1 { ('{', '}') } else { ('(', ')') })).collect()
+    }
+
+    // Every position of the game so far drawn as a board, starting from the initial setup, each
+    // frame headed by a title line and separated from the next by a form feed
+    fn render_frames(&self) -> String {
+        let options = DisplayOptions { color: false, ..self.display_options }; // No escape codes in files
+        let height = self.board.height;
+        let mut board = self.starting_board();
+        let mut frames = vec![format!("Frame 0/{}: start{}", self.move_history.len(), board.render(None, &None, false, &options, &BTreeMap::new(), None))];
+
+        for (i, record) in self.move_history.iter().enumerate() {
+            let m = board.get_piece(record.from.0, record.from.1)
+                .and_then(|p| board.calculate_valid_moves(record.from.0, record.from.1, p).into_iter().find(|m| (m.to_r, m.to_c) == record.to));
+            match m {
+                Some(m) => { board.apply_move(record.from.0, record.from.1, &m); }
+                None => break, // Only possible if the rules changed during the game
+            }
+            frames.push(format!("Frame {}/{}: {}. {}{}", i + 1, self.move_history.len(), i / 2 + 1, record.notation(height),
+                board.render(Some(record.to), &None, false, &options, &BTreeMap::new(), None)));
+        }
+        frames.join("\x0c\n")
+    }
+
+    // Shows the board as it would be after a move, without playing it. Takes &self, so the
+    // real board, turn and history can't change.
+    fn preview_move(&self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> Result<(), MoveError> {
+        if self.game_over { return Err(MoveError::GameOver); }
+        let height = self.board.height;
+        let piece = self.board.get_piece(from_r, from_c).ok_or(MoveError::NoPiece(coords_to_algebraic(from_r, from_c, height)))?;
+        if piece.color != self.current_player { return Err(MoveError::WrongColor); }
+        if (from_r, from_c) == (to_r, to_c) { return Err(MoveError::SameSquare); }
+        let m = self.legal_moves_from(from_r, from_c, piece).into_iter().find(|m| m.to_r == to_r && m.to_c == to_c)
+            .ok_or(MoveError::IllegalDestination { piece, to: coords_to_algebraic(to_r, to_c, height) })?;
+
+        let mut preview = self.board.clone();
+        let captured = preview.apply_move(from_r, from_c, &m);
+        preview.display_marked(Some((to_r, to_c)), &None, false, &self.display_options, &BTreeMap::new(), self.fog_viewer());
+        out!("Preview: {} {}{}{}", piece, coords_to_algebraic(from_r, from_c, height),
+            if captured.is_some() { 'x' } else { '-' }, coords_to_algebraic(to_r, to_c, height));
+        match captured {
+            Some(p) if p.piece_type == PieceType::ProductOwner && !self.board.rules.play_on => outln!(", captures {} and wins the game.", p),
+            Some(p) => outln!(", captures {}.", p),
+            None => outln!("."),
+        }
+        outln!("Nothing was played; it is still {:?}'s turn.", self.current_player);
+        Ok(())
+    }
+
+    // Puzzle solver: every move that captures the enemy ProductOwner right now
+    fn display_mate_in_one(&self) {
+        let height = self.board.height;
+        let mates = self.board.owner_captures(self.current_player);
+        if mates.is_empty() {
+            outln!("No mate in 1 for {}.", self.player_label(self.current_player));
+            return;
+        }
+        outln!("Mate in 1 for {} ({} move{}):", self.player_label(self.current_player), mates.len(), if mates.len() == 1 { "" } else { "s" });
+        for ((r, c), m) in &mates {
+            let piece = self.board.grid[*r][*c].unwrap();
+            let how = if m.jumped_piece_coord.is_some() { "jumps over the ProductOwner" } else { "lands on the ProductOwner" };
+            outln!("  {} {}x{}  {}", piece, coords_to_algebraic(*r, *c, height), coords_to_algebraic(m.to_r, m.to_c, height), how);
+        }
+        if self.board.rules.play_on { outln!("(suddendeath is off, so taking the ProductOwner doesn't end the game by itself.)"); }
+    }
+
+    // Lists the squares a Developer crosses on a legal move, naming the piece it jumps over
+    fn show_developer_path(&self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> Result<(), String> {
+        let height = self.board.height;
+        let square = |r: usize, c: usize| coords_to_algebraic(r, c, height);
+        self.check_visible(from_r, from_c).map_err(|e| e.to_string())?;
+        let piece = self.board.get_piece(from_r, from_c).ok_or(MoveError::NoPiece(square(from_r, from_c))).map_err(|e| e.to_string())?;
+        if piece.piece_type != PieceType::Developer {
+            return Err(format!("Invalid input: path only follows Developers; {} holds a {:?}.", square(from_r, from_c), piece.piece_type));
+        }
+        let m = self.legal_moves_from(from_r, from_c, piece).into_iter().find(|m| m.to_r == to_r && m.to_c == to_c)
+            .ok_or_else(|| MoveError::IllegalDestination { piece, to: square(to_r, to_c) }.to_string())?;
+        let (dr, dc, dist) = Board::developer_vector((from_r, from_c), (to_r, to_c)).ok_or("Invalid move: not a straight Developer move.")?;
+
+        let line = if dr == 0 { "along the rank" } else if dc == 0 { "along the file" } else { "diagonally" };
+        outln!("Path of {} {}{}{} ({} square{} {}):", piece, square(from_r, from_c), if m.is_capture { 'x' } else { '-' },
+            square(to_r, to_c), dist, if dist == 1 { "" } else { "s" }, line);
+        for step in 1..=dist {
+            let (r, c) = self.board.offset_square(from_r, from_c, dr * step, dc * step).ok_or("Invalid move: the path leaves the board.")?;
+            let description = match self.board.get_piece(r, c) {
+                Some(p) if step == dist => format!("lands on {} and captures it", p),
+                Some(p) if m.jumped_piece_coord == Some((r, c)) => format!("jumps over {} and captures it", p),
+                Some(p) => format!("jumps over {}", p),
+                None if step == dist => "lands".to_string(),
+                None => "passes over an empty square".to_string(),
+            };
+            outln!("  {:<4}{}", square(r, c), description);
+        }
+        Ok(())
+    }
+
+    // Attaches a comment to the most recent move, replacing any earlier one. Braces and line
+    // breaks would end the comment early in the transcript, so they are replaced.
+    fn comment_last_move(&mut self, text: &str) -> Result<(), String> {
+        let record = self.move_history.last_mut().ok_or("Invalid input: There is no move to comment on yet.")?;
+        let text: String = text.chars()
+            .map(|ch| match ch { '{' => '(', '}' => ')', ch if ch.is_control() => ' ', ch => ch })
+            .collect();
+        let text = text.trim();
+        if text.is_empty() { return Err("Invalid input: The comment is empty.".to_string()); }
+        record.comment = Some(text.to_string());
+        Ok(())
+    }
+
+    fn display_mistakes(&self) {
+        if self.mistakes.is_empty() {
+            outln!("No illegal moves attempted yet.");
+        } else {
+            let tallies: Vec<String> = self.mistakes.iter().map(|(kind, count)| format!("{}: {}", kind, count)).collect();
+            outln!("{}.", tallies.join(", "));
+        }
+    }
+
+    fn try_move(&mut self, from_r: usize, from_c: usize, to_r: usize, to_c: usize) -> Result<(), MoveError> {
+        if self.game_over { return Err(MoveError::GameOver); }
+
+        if self.repeat_blocked_square() == Some((from_r, from_c)) {
+            return Err(MoveError::RepeatPiece(coords_to_algebraic(from_r, from_c, self.board.height)));
+        }
+
+        // Always work from the moves for the current position (cached per position, so this is cheap)
+        let current_valid_moves = match self.board.get_piece(from_r, from_c) {
+            Some(p) if p.color == self.current_player => self.legal_moves_from(from_r, from_c, p),
+            Some(_) => return Err(MoveError::WrongColor),
+            None => return Err(MoveError::NoPiece(coords_to_algebraic(from_r, from_c, self.board.height))),
+        };
+        // A selection made before the board changed (e.g. an analysis-mode edit) highlights moves that may
+        // no longer exist; refuse rather than guess which list the player meant
+        if self.selected_square_coords == Some((from_r, from_c))
+            && self.available_moves_for_selected.as_ref().is_some_and(|cached| *cached != current_valid_moves) {
+            self.selected_square_coords = None;
+            self.available_moves_for_selected = None;
+            return Err(MoveError::StaleSelection);
+        }
+
+        // Strict: say the move exposes the ProductOwner rather than that the piece can't go there
+        if !current_valid_moves.iter().any(|m| m.to_r == to_r && m.to_c == to_c) && self.strict_applies() {
+            let exposes = self.board.legal_moves(self.current_player).iter().any(|((r, c), m)| {
+                (*r, *c) == (from_r, from_c) && m.to_r == to_r && m.to_c == to_c && self.board.exposes_owner(*r, *c, m, self.current_player)
+            });
+            if exposes {
+                return Err(MoveError::ExposesOwner { capture: self.is_capture_under_threat(from_r, from_c, to_r, to_c) });
+            }
+        }
+
+        // Forced capture: explain why an otherwise valid quiet move is rejected
+        if self.board.rules.must_capture && !current_valid_moves.iter().any(|m| m.to_r == to_r && m.to_c == to_c) {
+            let captures = self.board.capturing_moves(self.current_player);
+            let is_quiet_move = self.board.get_piece(from_r, from_c).is_some_and(|p| {
+                self.board.calculate_valid_moves(from_r, from_c, p).iter().any(|m| m.to_r == to_r && m.to_c == to_c && !m.is_capture)
+            });
+            if is_quiet_move && !captures.is_empty() {
+                let capture_list: Vec<String> = captures.iter()
+                    .map(|((r, c), m)| format!("{}x{}", coords_to_algebraic(*r, *c, self.board.height), coords_to_algebraic(m.to_r, m.to_c, self.board.height)))
+                    .collect();
+                return Err(MoveError::MustCapture(capture_list.join(", ")));
+            }
+        }
+
+        let legal_move_count = self.current_legal_moves().len();
+        let before = self.board.clone();
+        match self.board.move_piece(from_r, from_c, to_r, to_c, self.current_player, &current_valid_moves) {
+            Ok(captured_piece_option) => {
+                self.takeback_position = Some(before);
+                if let Some(piece) = self.board.get_piece(to_r, to_c) {
+                    self.events.push(GameEvent::MoveMade { player: self.current_player, piece, from: (from_r, from_c), to: (to_r, to_c) });
+                    self.move_history.push(MoveRecord {
+                        player: self.current_player,
+                        piece,
+                        from: (from_r, from_c),
+                        to: (to_r, to_c),
+                        captured: captured_piece_option,
+                        legal_move_count,
+                        comment: None,
+                        decided: false,
+                    });
+                }
+                let moved_piece_symbol = self.board.get_piece(to_r, to_c).map_or("?".to_string(), |p| p.to_string());
+                let mut message = format!("Moved {} from {} to {}.", moved_piece_symbol, coords_to_algebraic(from_r, from_c, self.board.height), coords_to_algebraic(to_r, to_c, self.board.height));
+                if let Some(captured) = captured_piece_option {
+                    message.push_str(&format!(" Captured {}.", captured));
+                    let square = current_valid_moves.iter().find(|m| (m.to_r, m.to_c) == (to_r, to_c))
+                        .and_then(|m| m.jumped_piece_coord).unwrap_or((to_r, to_c));
+                    self.events.push(GameEvent::CaptureMade { player: self.current_player, captured, square });
+                    let reached_target = self.board.rules.capture_target.filter(|&target| self.captures_by(self.current_player) >= target);
+                    // Without sudden death a ProductOwner capture is just recorded, and the game ends when a side is wiped out
+                    let decisive = if self.board.rules.play_on { !self.board.has_pieces(self.current_player.opponent()) }
+                        else { captured.piece_type == PieceType::ProductOwner };
+                    let reason = match reached_target {
+                        _ if decisive && self.board.rules.play_on => Some(EndReason::PiecesCaptured),
+                        _ if decisive => Some(EndReason::OwnerCaptured),
+                        Some(target) => Some(EndReason::CaptureTarget(target)),
+                        None => None,
+                    };
+                    if let Some(reason) = reason {
+                        self.game_over = true;
+                        self.winner = Some(self.current_player);
+                        self.events.push(GameEvent::GameEnded { result: self.result(), reason });
+                        if let Some(record) = self.move_history.last_mut() {
+                            record.decided = true;
+                            message.push_str(&format!(" Decisive move: {}.", record.notation(self.board.height)));
+                        }
+                        // Game over message will be handled by display_turn_info
+                    }
+                }
+                if let Some(limit) = self.board.rules.no_progress_limit.filter(|&limit| !self.game_over && self.plies_without_capture() >= limit) {
+                    self.game_over = true; // Drawn: winner stays None
+                    self.events.push(GameEvent::GameEnded { result: GameResult::Draw, reason: EndReason::NoProgress(limit) });
+                }
+                if !self.game_over && !self.board.owner_captures(self.current_player).is_empty() {
+                    self.events.push(GameEvent::OwnerAttacked { by: self.current_player });
+                }
+                if !self.quiet { outln!("{}", message); }
+                // Only a capture can take a side down to its ProductOwner, so checking here announces each drop once
+                // rather than every turn after it. A takeback or the setup editor may give pieces back; losing
+                // them again is a new drop and is announced again.
+                let loser = self.current_player.opponent();
+                let lone_owner = captured_piece_option.is_some_and(|p| p.piece_type != PieceType::ProductOwner)
+                    && self.board.find_owner(loser).is_some()
+                    && self.board.grid.iter().flatten().flatten().filter(|p| p.color == loser).count() == 1;
+                if lone_owner && !self.game_over && !self.quiet {
+                    outln!("{} has only their ProductOwner remaining.", self.player_label(loser));
+                }
+                let fork = if self.game_over || self.quiet { None } else { self.fork_message(to_r, to_c) };
+                if let Some(fork) = fork { outln!("{}", fork); }
+                
+                if !self.game_over {
+                    self.switch_player();
+                }
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Parses a square typed by the player, using the same rank numbering as the display (see zero_ranks)
+fn algebraic_to_coords(s: &str, board_height: usize, board_width: usize) -> Result<(usize, usize), String> {
+    parse_square(s, board_height, board_width, rank_base())
+}
+
+fn parse_square(s: &str, board_height: usize, board_width: usize, base: usize) -> Result<(usize, usize), String> {
+    if s.len() < 2 { return Err(format!("Invalid coordinate format: {}", s)); }
+    let mut chars = s.chars();
+    let col_char = chars.next().unwrap().to_ascii_uppercase();
+    let row_str: String = chars.collect();
+    let col_idx = (col_char as u8).wrapping_sub(b'A') as usize;
+    let row_num = match row_str.parse::<usize>() {
+        Ok(n) => n,
+        Err(_) => return Err(format!("Invalid row number in coordinate: {}", s)),
+    };
+    if row_num < base || row_num - base >= board_height {
+        return Err(format!("Row number {} out of bounds ({}-{}).", row_num, base, board_height - 1 + base));
+    }
+    let row_idx = row_num - base;
+    if col_idx >= board_width { return Err(format!("Column {} out of bounds (A-{}).", col_char, (b'A' + board_width as u8 - 1) as char)); }
+    Ok((row_idx, col_idx))
+}
+
+fn rank_base() -> usize {
+    if ACTIVE_DISPLAY.with(|active| active.get().zero_ranks) { 0 } else { 1 }
+}
+
+fn coords_to_algebraic(r: usize, c: usize, board_height: usize) -> String {
+    format_square(r, c, board_height, rank_base())
+}
+
+fn format_square(r: usize, c: usize, board_height: usize, base: usize) -> String {
+    let digits = if ACTIVE_DISPLAY.with(|active| active.get().coord_pad) { board_height.to_string().len() } else { 1 };
+    format!("{}{:0digits$}", (b'A' + c as u8) as char, r + base, digits = digits)
+}
+
+// Splits a command line into at most `max_parts` arguments. The first `max_parts - 1` are split on
+// whitespace and the remainder is kept as one free-text argument (surrounding quotes are removed),
+// e.g. `name white "The Grandmaster"` -> ["name", "white", "The Grandmaster"].
+fn split_args(line: &str, max_parts: usize) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() && args.len() + 1 < max_parts {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        args.push(rest[..end].to_string());
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        let unquoted = rest.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(rest);
+        args.push(unquoted.to_string());
+    }
+    args
+}
+
+// Handles "<command> [on|off]": applies the value if one is given and reports the current setting.
+// Returns true if the setting was changed.
+fn apply_toggle(parts: &[&str], label: &str, setting: &mut bool) -> bool {
+    match parts.get(1).and_then(|s| parse_toggle(s)) {
+        Some(value) if parts.len() == 2 => {
+            *setting = value;
+            outln!("{}: {}", label, on_off(value));
+            true
+        }
+        _ => {
+            outln!("{}: {}", label, on_off(*setting));
+            outln!("Usage: {} <on|off>", parts[0].to_lowercase());
+            false
+        }
+    }
+}
+
+// Splits a single move token like "B1-C3" or "B1xC3" into (from, to, written_as_capture)
+fn split_move_token(token: &str) -> Option<(&str, &str, bool)> {
+    let sep = token.find(['-', 'x', 'X'])?;
+    let (from, to) = (&token[..sep], &token[sep + 1..]);
+    if from.is_empty() || to.is_empty() { return None; }
+    Some((from, to, token[sep..].starts_with(['x', 'X'])))
+}
+
+// Parses an "on"/"off" argument for toggle commands
+fn parse_toggle(s: &str) -> Option<bool> {
+    match s.to_lowercase().as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_color(s: &str) -> Option<PlayerColor> {
+    match s.to_lowercase().as_str() {
+        "white" => Some(PlayerColor::White),
+        "black" => Some(PlayerColor::Black),
+        _ => None,
+    }
+}
+
+// Piece types a handicap may add (a second ProductOwner is never allowed)
+fn parse_extra_piece(s: &str) -> Option<PieceType> {
+    parse_piece_type(s).filter(|piece_type| *piece_type != PieceType::ProductOwner)
+}
+
+// A piece type from its notation letter (O, D or S, either case; see Piece::letter) or its name
+fn parse_piece_type(s: &str) -> Option<PieceType> {
+    match s.to_lowercase().as_str() {
+        "o" | "productowner" => Some(PieceType::ProductOwner),
+        "d" | "developer" => Some(PieceType::Developer),
+        "s" | "designer" => Some(PieceType::Designer),
+        _ => None,
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn get_board_dimension(prompt: &str) -> usize {
+    loop {
+        out!("{}", prompt);
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        match input.trim().parse::<usize>() {
+            Ok(val) if (MIN_DIM..=MAX_DIM).contains(&val) => return val,
+            _ => outln!("Invalid input. Please enter a number between {} and {}.", MIN_DIM, MAX_DIM),
+        }
+    }
+}
+
+// Accepts "B1 C3" as well as the single-token forms "B1-C3" and "B1xC3".
+// Returns (from, to, written_as_capture).
+fn parse_move_args<'a>(args: &[&'a str]) -> Option<(&'a str, &'a str, bool)> {
+    match args {
+        [from, to] => Some((from, to, false)),
+        [token] => split_move_token(token),
+        _ => None,
+    }
+}
+
+// Canonical form of a command line for echo: a move's squares in capitals with an arrow between them
+// (a capture written with 'x' keeps its 'x'), anything else lowercased with its arguments as typed
+fn interpret_command(command: &str, args: &[&str]) -> String {
+    let dry = args.last().is_some_and(|arg| arg.eq_ignore_ascii_case("--dry"));
+    let squares = if dry { &args[..args.len() - 1] } else { args };
+    match parse_move_args(squares).filter(|_| command == "move") {
+        Some((from, to, marked_capture)) => format!("move {} {} {}{}", from.to_uppercase(), if marked_capture { "x" } else { "->" },
+            to.to_uppercase(), if dry { " (dry run)" } else { "" }),
+        None => std::iter::once(command).chain(args.iter().copied()).collect::<Vec<_>>().join(" "),
+    }
+}
+
+// Selects a piece for the "select" command and prints its moves or why it can't be selected
+fn select_and_report(game_state: &mut GameState, r: usize, c: usize) {
+    let height = game_state.board.height;
+    match game_state.select_piece(r, c) {
+        Ok(moves) => {
+            let piece = game_state.board.get_piece(r, c).map_or("?".to_string(), |p| p.to_string());
+            if moves.is_empty() {
+                outln!("Selected: {} at {}. No available moves.", piece, coords_to_algebraic(r, c, height));
+            } else if let Some(groups) = Some(&moves).filter(|_| game_state.settings.group_moves).and_then(|moves| compass_groups((r, c), moves, height)) {
+                outln!("Selected: {} at {}. Available moves: {}", piece, coords_to_algebraic(r, c, height), groups);
+            } else {
+                let targets: Vec<String> = moves.iter().map(|m| coords_to_algebraic(m.to_r, m.to_c, height)).collect();
+                outln!("Selected: {} at {}. Available moves: {}", piece, coords_to_algebraic(r, c, height), targets.join(", "));
+            }
+            if game_state.settings.show_attackers {
+                let attackers: Vec<String> = game_state.board.attacker_squares(r, c, game_state.current_player.opponent()).into_iter()
+                    .filter_map(|(ar, ac)| game_state.board.get_piece(ar, ac).map(|p| format!("{} at {}", p, coords_to_algebraic(ar, ac, height))))
+                    .collect();
+                if attackers.is_empty() { outln!("Not attacked."); } else { outln!("Attacked by: {}", attackers.join(", ")); }
+            }
+        }
+        Err(MoveError::WrongColor) => outln!("Invalid input: You cannot select a {} piece on {:?}'s turn.",
+            format!("{:?}", game_state.current_player.opponent()).to_lowercase(), game_state.current_player),
+        Err(MoveError::NoPiece(square)) => outln!("Invalid input: There is no piece at {}.", square),
+        Err(e) => outln!("{}", e),
+    }
+}
+
+// Destinations grouped by compass direction from the piece, e.g. "N: B2, B3 | NE: C2", nearest first.
+// North is towards Black's side. None if a move isn't along a straight line (the Designer's L-shapes).
+fn compass_groups(from: (usize, usize), moves: &[MoveDetail], height: usize) -> Option<String> {
+    // DIRECTIONS in clockwise order, starting from north
+    const COMPASS: [(isize, isize); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+    let mut groups: Vec<Vec<(isize, String)>> = vec![Vec::new(); COMPASS.len()];
+    for m in moves {
+        let (dr, dc, dist) = Board::developer_vector(from, (m.to_r, m.to_c))?;
+        let index = COMPASS.iter().position(|direction| *direction == (dr, dc))?;
+        groups[index].push((dist, coords_to_algebraic(m.to_r, m.to_c, height)));
+    }
+    let listed: Vec<String> = COMPASS.iter().zip(groups.iter_mut()).filter(|(_, group)| !group.is_empty()).map(|((dr, dc), group)| {
+        group.sort();
+        format!("{}: {}", direction_name(*dr, *dc), group.iter().map(|(_, square)| square.as_str()).collect::<Vec<_>>().join(", "))
+    }).collect();
+    Some(listed.join(" | "))
+}
+
+// Handles "preview <from> <to>" and "move <from> <to> --dry"
+fn preview_command(game_state: &GameState, args: &[&str]) {
+    match parse_move_args(args) {
+        Some((from_str, to_str, _)) => {
+            let height = game_state.board.height;
+            let width = game_state.board.width;
+            match (algebraic_to_coords(from_str, height, width), algebraic_to_coords(to_str, height, width)) {
+                (Ok((from_r, from_c)), Ok((to_r, to_c))) => {
+                    if let Err(e) = game_state.preview_move(from_r, from_c, to_r, to_c) { outln!("{}", e); }
+                }
+                (Err(_), _) => outln!("Invalid input: {} is not a valid 'from' square.", from_str.to_uppercase()),
+                (_, Err(_)) => outln!("Invalid input: {} is not a valid 'to' square.", to_str.to_uppercase()),
+            }
+        }
+        None => outln!("Usage: preview <from> <to> (or move <from> <to> --dry)"),
+    }
+}
+
+// Asks a yes/no question on stdin; anything but y/yes (including end of input) counts as no
+fn confirm(question: &str) -> bool {
+    out!("{} (y/n) ", question);
+    let answer = read_input_line().unwrap_or_default();
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+// Reads a line typed by the player, or None at the end of input. Output collected by execute_command
+// is printed first so the question being answered is on screen.
+fn read_input_line() -> Option<String> {
+    if let Some(text) = CAPTURED_OUTPUT.with(|captured| captured.borrow_mut().as_mut().map(std::mem::take)) { print!("{}", text); }
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input),
+    }
+}
+
+// Short explanation of how each piece moves and captures (shared by help and the tutorial)
+fn piece_rules(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::ProductOwner => "The ProductOwner moves 1 square in any direction and captures by landing on an enemy piece. Lose it and you lose the game!",
+        PieceType::Designer => "The Designer moves in an L-shape (2 squares one way, 1 square sideways) and captures by landing on an enemy piece.",
+        PieceType::Developer => "The Developer moves up to 3 squares in a straight line (any direction) and must land on an empty square. It captures by jumping OVER a single enemy piece.",
+    }
+}
+
+// Usage and English description of each command listed by "help". A --lang file can replace a
+// description under the key help_key gives its usage, e.g. help.ai.depth for "ai depth <n>".
+const HELP_COMMANDS: [(&str, &str); 83] = [
+    ("move <from> <to>", "Move a piece (e.g. move B1 C3, move B1-C3 or move B1xC3)"),
+    ("select <square>", "Highlight piece (e.g. select B1)"),
+    ("deselect", "Clear the selected piece and its move markers"),
+    ("groupmoves <on|off>", "List a selected Developer's or ProductOwner's moves by direction (N, NE, ...)"),
+    ("lockselection <on|off>", "Once a piece is selected, only accept moving it, another select, or deselect"),
+    ("preview <from> <to>", "Show the board after a move without playing it (or move ... --dry)"),
+    ("matein1", "List the moves that capture the enemy ProductOwner right now"),
+    ("path <from> <to>", "List the squares a Developer crosses on a move and what it jumps"),
+    ("name <color> <name>", "Set a player's name (e.g. name white \"The Grandmaster\")"),
+    ("size", "Show the board dimensions and coordinate range"),
+    ("analysis <on|off>", "Analysis mode, for exploring positions outside normal play"),
+    ("setup [empty]", "Edit the position by hand (put, clear, done, cancel), starting from this one or an empty board"),
+    ("turn <white|black>", "Set the side to move without moving (analysis mode only)"),
+    ("branching", "Show legal move count and average branching factor"),
+    ("longest", "Show your longest legal move and how far each piece type can reach"),
+    ("stuck", "List your pieces that are blocked in and can't move at all"),
+    ("hint", "Suggest a move for the current player"),
+    ("transcript", "Print the game so far as a replayable transcript"),
+    ("comment <text>", "Annotate the last move (shown in braces in the transcript)"),
+    ("autosave <file|on|off>", "Save the transcript to a file after every move (resume with --resume <file>)"),
+    ("frames [file]", "Write every position of the game to a file, one board per frame"),
+    ("csv <file>", "Write the board to a file as comma-separated rows (WD = White Developer, empty = no piece)"),
+    ("id", "Show this game's id (also in the transcript and default file names)"),
+    ("peek", "View the board once from your opponent's side"),
+    ("defense", "Mark which of your pieces another of your pieces could recapture"),
+    ("coverage", "Mark the empty squares your pieces can move to, and which several can reach"),
+    ("heatmap [white|black]", "Show how many of a side's pieces can move to each empty square"),
+    ("wincaptures <n|off>", "Also win by capturing n enemy pieces (default: off)"),
+    ("noprogress <n|off>", "Draw after n plies in a row without a capture (default: off)"),
+    ("suddendeath <on|off>", "off: keep playing after a ProductOwner falls until a side has no pieces"),
+    ("takeback", "Ask your opponent to let you take back your last move"),
+    ("draw", "Offer a draw; the game ends if your opponent accepts"),
+    ("variants", "List every rule variant with its current setting"),
+    ("progress [on|off]", "Show plies left before a no-progress draw (on: with every turn)"),
+    ("handicap <color> <piece>", "Give a side an extra developer or designer (or: handicap off; before the first move)"),
+    ("closesetup <n|off>", "Start the back ranks n rows apart, for tall boards (before the first move)"),
+    ("corners <layout>", "Starting corners: standard, mirrored, sameside or random (before the first move)"),
+    ("mistakes", "Count rejected moves by reason"),
+    ("events", "List the game's events: moves, captures, attacks on a ProductOwner, draw offers and the result"),
+    ("reach <square>", "Explain how far a Developer can go in each direction"),
+    ("tree <square>", "List a piece's moves with the threats each one leaves, safest first"),
+    ("validate", "Check the current position for inconsistencies"),
+    ("bench [depth] [iterations]", "Time move generation and perft on the starting position (default 4 3)"),
+    ("perftdivide <depth>", "Count move sequences from this position, split by first move"),
+    ("ai <white|black|off>", "Let the computer play a side"),
+    ("ai depth <n>", "How many plies the computer looks ahead (1-{max})"),
+    ("ai table <on|off>", "Let the computer remember positions it has already searched"),
+    ("ai stats", "Show search counters for the computer's last move"),
+    ("ai weight <term> <n>", "Weight of an evaluation term: material, mobility, center or safety"),
+    ("compare <n> <a> <b>", "Play n games between two AI configs, e.g. depth=2 vs mobility=5,center=0, and show the score"),
+    ("eval", "Show the computer's evaluation of the position, term by term"),
+    ("material", "Count the pieces each side has left (O ProductOwner, D Developer, S Designer)"),
+    ("aitime <ms>", "Maximum time the computer may think per move"),
+    ("movetime <ms>", "Same as aitime"),
+    ("auto <on|off>", "Auto-select the piece when it is the only one that can move"),
+    ("winalert <on|off>", "Say \"You can win this turn!\" when you can capture the enemy ProductOwner"),
+    ("verbose <on|off>", "Explain why the computer picked each move"),
+    ("echo <on|off>", "Repeat each command as it was understood, e.g. (interpreted: move B1 -> C3)"),
+    ("attackers <on|off>", "When you select a piece, say which enemy pieces could capture it"),
+    ("warnhang <on|off>", "Ask before a move that leaves your ProductOwner capturable"),
+    ("scoring <on|off>", "Show the winner's tournament score in the game summary"),
+    ("score", "Tournament score: {win} for a win plus {weight} x the winner's remaining material"),
+    ("color <on|off>", "Use ANSI colors in the display"),
+    ("checker <on|off>", "Shade dark empty squares like a chessboard (needs color)"),
+    ("compact <on|off>", "Draw two characters per square to fit wide boards"),
+    ("border <style>", "Frame around the board: ascii, unicode (box-drawing lines) or none"),
+    ("labels <files|ranks> <side>", "Where coordinates go: files top/bottom/both/none, ranks left/right/both/none"),
+    ("ranktop <on|off>", "Draw rank 1 at the top of the board (files stay in order)"),
+    ("marker <kind> <chars>", "Set the quiet, capture or select (two characters) marker"),
+    ("theme <name>", "Piece symbols: unicode, emoji or ascii"),
+    ("coordpad <on|off>", "Zero-pad ranks (A01) so squares line up on boards with 10+ rows"),
+    ("zeroranks <on|off>", "Number ranks from 0 like the grid; typed squares use it too, transcripts don't"),
+    ("tutorial", "Learn how each piece moves"),
+    ("dev_land_capture <on|off>", "Let Developers also capture an adjacent enemy by landing on it"),
+    ("dev_orthogonal <on|off>", "Let Developers move only along ranks and files (no diagonals)"),
+    ("norepeatpiece <on|off>", "Forbid moving the same piece on two turns in a row (unless nothing else can move)"),
+    ("strict <on|off>", "Forbid moves that leave your ProductOwner open to capture"),
+    ("mustcapture <on|off>", "Require a capture whenever one is available"),
+    ("fog <on|off>", "Only show your pieces and the squares they can reach"),
+    ("designer <shape>", "Set the Designer's move shape: standard or extended (adds the 1-3 L)"),
+    ("restart [default]", "Restart the match at the current size, or at the launch size with \"default\""),
+    ("exit", "Exit the game"),
+    ("help", "Show this list"),
+];
+
+// The words of a usage before its arguments, "ai depth <n>" -> ["ai", "depth"]
+fn command_words(usage: &str) -> Vec<&str> {
+    usage.split_whitespace().take_while(|w| !w.starts_with(['<', '['])).collect()
+}
+
+// Message key of a command's help description: the command words of its usage, "ai depth <n>" -> "help.ai.depth"
+fn help_key(usage: &str) -> String {
+    format!("help.{}", command_words(usage).join("."))
+}
+
+// Every command in HELP_COMMANDS as (command words, required arguments, optional arguments), e.g.
+// ("bench", 0, 2). run_command only dispatches commands listed here, so the list can't fall out of date.
+fn command_inventory() -> Vec<(String, usize, usize)> {
+    HELP_COMMANDS.iter().map(|(usage, _)| {
+        let args: Vec<&str> = usage.split_whitespace().skip(command_words(usage).len()).collect();
+        (command_words(usage).join(" "), args.iter().filter(|a| a.starts_with('<')).count(), args.iter().filter(|a| a.starts_with('[')).count())
+    }).collect()
+}
+
+fn print_help() {
+    outln!("{}", tr("help.title", &[]));
+    let args: [(&str, &dyn fmt::Display); 3] = [("max", &MAX_AI_DEPTH), ("win", &TOURNAMENT_WIN_POINTS), ("weight", &TOURNAMENT_MATERIAL_WEIGHT)];
+    for (usage, _) in HELP_COMMANDS {
+        outln!("  {:<28}{}", usage, tr(&help_key(usage), &args));
+    }
+    outln!("{}", tr("help.pieces", &[]));
+    for piece_type in [PieceType::ProductOwner, PieceType::Designer, PieceType::Developer] {
+        outln!("  {}  {}", Piece::new(piece_type, PlayerColor::White), piece_rules(piece_type));
+    }
+}
+
+// A scripted tutorial step: one White piece to move and one enemy piece to practice on
+struct TutorialLesson {
+    piece_type: PieceType,
+    square: (usize, usize),
+    enemy_type: PieceType,
+    enemy_square: (usize, usize),
+    hint: &'static str,
+}
+
+// Guided walkthrough: each lesson sets up a tiny scripted board and waits for a legal move with one piece type.
+// Returns false if the player left the tutorial early.
+fn run_tutorial() -> bool {
+    let lessons = [
+        TutorialLesson { piece_type: PieceType::ProductOwner, square: (2, 2), enemy_type: PieceType::Developer, enemy_square: (3, 3),
+            hint: "Try capturing the enemy Developer on D4: move C3 D4" },
+        TutorialLesson { piece_type: PieceType::Designer, square: (1, 1), enemy_type: PieceType::Developer, enemy_square: (3, 2),
+            hint: "Try capturing the enemy Developer on C4: move B2 C4" },
+        TutorialLesson { piece_type: PieceType::Developer, square: (1, 1), enemy_type: PieceType::Designer, enemy_square: (2, 2),
+            hint: "Try jumping over the enemy Designer on C3 to land on D4: move B2 D4" },
+    ];
+
+    outln!("Welcome to the tutorial! Type \"skip\" to skip a lesson or \"exit\" to leave.");
+    for lesson in lessons.iter() {
+        let (start_r, start_c) = lesson.square;
+        let mut board = Board::empty(MIN_DIM, MIN_DIM);
+        let piece = Piece::new(lesson.piece_type, PlayerColor::White);
+        board.set_square(start_r, start_c, Some(piece));
+        board.set_square(lesson.enemy_square.0, lesson.enemy_square.1, Some(Piece::new(lesson.enemy_type, PlayerColor::Black)));
+        let valid_moves = board.calculate_valid_moves(start_r, start_c, piece);
+
+        outln!();
+        outln!("Lesson: {:?} {}", lesson.piece_type, piece);
+        outln!("{}", piece_rules(lesson.piece_type));
+        board.display(Some((start_r, start_c)), &Some(valid_moves.clone()), false, &DisplayOptions::default());
+        outln!("{}", lesson.hint);
+
+        loop {
+            out!("tutorial> ");
+            let Some(input) = read_input_line() else { return false; };
+            let parts: Vec<&str> = input.split_whitespace().collect();
+            if parts.is_empty() { continue; }
+
+            match parts[0].to_lowercase().as_str() {
+                "exit" => return false,
+                "skip" => break,
+                "move" if parts.len() == 3 => {
+                    match (algebraic_to_coords(parts[1], board.height, board.width),
+                           algebraic_to_coords(parts[2], board.height, board.width)) {
+                        (Ok((from_r, from_c)), Ok((to_r, to_c))) => {
+                            if (from_r, from_c) != (start_r, start_c) {
+                                outln!("Move the {} on {} for this lesson.", piece, coords_to_algebraic(start_r, start_c, board.height));
+                                continue;
+                            }
+                            match board.move_piece(from_r, from_c, to_r, to_c, PlayerColor::White, &valid_moves) {
+                                Ok(captured) => {
+                                    board.display(None, &None, false, &DisplayOptions::default());
+                                    match captured {
+                                        Some(captured) => outln!("Well done! You captured {}.", captured),
+                                        None => outln!("Well done! That was a legal move (try the capture next time)."),
+                                    }
+                                    break;
+                                }
+                                Err(e) => outln!("{}", e),
+                            }
+                        }
+                        _ => outln!("Invalid input: Please enter coordinates from {} to {}.",
+                            coords_to_algebraic(0, 0, board.height), coords_to_algebraic(board.height - 1, board.width - 1, board.height)),
+                    }
+                }
+                _ => outln!("Usage: move <from_square> <to_square> (or \"skip\" / \"exit\")"),
+            }
+        }
+    }
+    outln!();
+    outln!("Tutorial complete! You know how every piece moves.");
+    true
+}
+
+// Rule variants listed by the variants command: Rules tag key, the command that changes it, description
+const VARIANTS: [(&str, &str, &str); 13] = [
+    ("devland", "dev_land_capture", "Developers may also capture an adjacent enemy by landing on it"),
+    ("mustcapture", "mustcapture", "A capture must be made whenever one is available"),
+    ("designer", "designer", "Designer move shape: standard L, or extended L"),
+    ("corners", "corners", "Starting corners: standard, mirrored or sameside"),
+    ("wincaptures", "wincaptures", "Also win by capturing this many enemy pieces"),
+    ("fog", "fog", "Only see your pieces and the squares they can reach"),
+    ("noprogress", "noprogress", "Draw after this many plies in a row without a capture"),
+    ("suddendeath", "suddendeath", "Capturing a ProductOwner ends the game (off: play until a side has no pieces)"),
+    ("devortho", "dev_orthogonal", "Developers move only along ranks and files, not diagonally"),
+    ("handicap", "handicap", "One side starts with an extra Developer or Designer on its back rank"),
+    ("closesetup", "closesetup", "Start Black's back rank this many rows above White's instead of on the far edge"),
+    ("norepeat", "norepeatpiece", "The piece a side just moved can't move again on its next turn (unless nothing else can)"),
+    ("strict", "strict", "Moves that leave your ProductOwner open to capture are illegal (unless every move does)"),
+];
+
+fn display_variants(rules: Rules) {
+    outln!("Rule variants (change one with its command, e.g. \"fog on\"):");
+    for item in rules.to_tag().split_whitespace() {
+        let (key, value) = item.split_once('=').unwrap_or((item, "?"));
+        match VARIANTS.iter().find(|(tag, _, _)| *tag == key) {
+            Some((_, command, description)) => outln!("  {:<18}{:<10}{}", command, value, description),
+            None => outln!("  {:<18}{:<10}", key, value),
+        }
+    }
+}
+
+const SETUP_USAGE: &str = "Commands: put <O|D|S> <white|black> <square>, clear <square|all>, done [white|black] to play from here, cancel.";
+
+// Commands still accepted while lockselection holds a selected piece
+const LOCKED_SELECTION_COMMANDS: [&str; 6] = ["move", "select", "deselect", "lockselection", "help", "exit"];
+
+// Commands still accepted once the game has ended: leaving, starting over, and looking back at the game
+const AFTER_GAME_COMMANDS: [&str; 13] = ["restart", "exit", "help", "transcript", "frames", "csv", "comment", "mistakes", "events", "validate", "score", "id", "material"];
+
+// Placement followed by the side to move, as read by --query and the transcript's Position tag
+fn position_with_side(board: &Board, to_move: PlayerColor) -> String {
+    format!("{} {}", board.to_notation(), if to_move == PlayerColor::White { 'w' } else { 'b' })
+}
+
+// Reads position_with_side's format into a board with the given rules and the side to move
+fn starting_position(text: &str, rules: Rules) -> Result<(Board, PlayerColor), String> {
+    let (placement, side) = text.split_once(' ').ok_or(format!("Invalid position: {}", text))?;
+    let to_move = match side {
+        "w" => PlayerColor::White,
+        "b" => PlayerColor::Black,
+        _ => return Err(format!("Invalid position: unknown side to move '{}'.", side)),
+    };
+    let mut board = Board::from_notation(placement)?;
+    board.rules = rules;
+    board.set_designer_shape(rules.designer_shape);
+    Ok((board, to_move))
+}
+
+// Ply limit for each random game played by --verify
+const MAX_VERIFY_PLIES: usize = 200;
+
+// Engine query mode: "--query <placement> [w|b] [--depth N] [--max-pieces N [--lenient]] [--black-upper]"
+// searches the position, prints the best move and its evaluation, and returns the process exit code (1 for
+// a bad position or no legal moves). A side with more than --max-pieces pieces makes the position invalid,
+// unless --lenient is given: then the extras are removed (see Board::extra_pieces) with a warning.
+// --black-upper reads the placement with Black's letters uppercase (see NotationCase).
+fn run_query(args: &[String]) -> i32 {
+    let mut notation = None;
+    let mut to_move = PlayerColor::White;
+    let mut depth = AiConfig::default().depth;
+    let mut max_pieces = None;
+    let mut lenient = false;
+    let mut case = NotationCase::default();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--query" if i + 1 < args.len() => { notation = Some(args[i + 1].clone()); i += 1; }
+            "--depth" if i + 1 < args.len() => {
+                match args[i + 1].parse::<u32>() {
+                    Ok(d) if (1..=MAX_AI_DEPTH).contains(&d) => depth = d,
+                    _ => { eprintln!("Invalid input: --depth takes a number from 1 to {}.", MAX_AI_DEPTH); return 1; }
+                }
+                i += 1;
+            }
+            "--max-pieces" if i + 1 < args.len() => {
+                match args[i + 1].parse::<usize>() {
+                    Ok(n) if n > 0 => max_pieces = Some(n),
+                    _ => { eprintln!("Invalid input: --max-pieces takes a number of at least 1."); return 1; }
+                }
+                i += 1;
+            }
+            "--lenient" => lenient = true,
+            "--black-upper" => case = NotationCase::BlackUpper,
+            "w" | "white" => to_move = PlayerColor::White,
+            "b" | "black" => to_move = PlayerColor::Black,
+            other => { eprintln!("Invalid input: unexpected argument '{}'.", other); return 1; }
+        }
+        i += 1;
+    }
+
+    // The side to move may also follow the placement inside the quoted notation
+    let notation = notation.unwrap_or_default();
+    let mut fields = notation.split_whitespace();
+    let placement = fields.next().unwrap_or("");
+    match fields.next() {
+        Some("w") => to_move = PlayerColor::White,
+        Some("b") => to_move = PlayerColor::Black,
+        Some(other) => { eprintln!("Invalid position: unknown side to move '{}'.", other); return 1; }
+        None => {}
+    }
+    let mut board = match Board::from_notation_with(placement, case) {
+        Ok(board) => board,
+        Err(e) => { eprintln!("Invalid position: {}", e); return 1; }
+    };
+    if lenient && max_pieces.is_none() {
+        eprintln!("Invalid input: --lenient only applies together with --max-pieces.");
+        return 1;
+    }
+    if let Some(max) = max_pieces {
+        if lenient {
+            for (piece, (r, c)) in board.cull_extras(max) {
+                eprintln!("Removed {:?} {:?} on {} (more than {} pieces).", piece.color, piece.piece_type, format_square(r, c, board.height, 1), max);
+            }
+        } else if let Some(color) = [PlayerColor::White, PlayerColor::Black].into_iter().find(|&color| !board.extra_pieces(color, max).is_empty()) {
+            eprintln!("Invalid position: {:?} has more than {} pieces (use --lenient to remove the extras).", color, max);
+            return 1;
+        }
+    }
+
+    let candidates = board.legal_moves(to_move);
+    if candidates.is_empty() {
+        eprintln!("{:?} has no legal moves.", to_move);
+        return 1;
+    }
+    let config = AiConfig { depth, time_budget_ms: 60_000, ..AiConfig::default() };
+    let (choice, stats) = choose_ai_move(&board, to_move, &candidates, &config);
+    let ((from_r, from_c), m) = match choice {
+        Some(mv) => mv,
+        None => { eprintln!("{:?} has no legal moves.", to_move); return 1; }
+    };
+    outln!("bestmove {}{}{}", coords_to_algebraic(from_r, from_c, board.height),
+        if m.is_capture { 'x' } else { '-' }, coords_to_algebraic(m.to_r, m.to_c, board.height));
+    outln!("eval {}", stats.score.map_or("?".to_string(), |s| s.to_string()));
+    outln!("depth {}", stats.depth_reached);
+    0
+}
+
+// Self-check mode: "--verify [games] [--seed N]" plays random games on random board sizes and
+// variants, and in every position compares each piece's moves from calculate_valid_moves (and from
+// fill_valid_moves with a reused buffer) against reference_valid_moves. Returns 1 on the first difference.
+fn run_verify(args: &[String]) -> i32 {
+    let mut games = 200;
+    let mut seed = 1;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--verify" => {
+                if let Some(n) = args.get(i + 1).and_then(|s| s.parse::<usize>().ok()) { games = n; i += 1; }
+            }
+            "--seed" => {
+                match args.get(i + 1).and_then(|s| s.parse::<u64>().ok()) {
+                    Some(n) => seed = n,
+                    None => { eprintln!("Invalid input: --seed takes a number."); return 1; }
+                }
+                i += 1;
+            }
+            other => { eprintln!("Invalid input: unexpected argument '{}'.", other); return 1; }
+        }
+        i += 1;
+    }
+
+    let mut rng = Rng::new(seed);
+    let mut positions = 0;
+    let mut buffer = Vec::new();
+    let by_square = |mut moves: Vec<MoveDetail>| { moves.sort_by_key(|m| (m.to_r, m.to_c)); moves };
+    for _ in 0..games {
+        let rules = Rules {
+            dev_land_capture: rng.below(2) == 0,
+            designer_shape: if rng.below(2) == 0 { DesignerShape::Standard } else { DesignerShape::Extended },
+            dev_orthogonal: rng.below(2) == 0,
+            ..Rules::default()
+        };
+        let (width, height) = (MIN_DIM + rng.below(MAX_DIM - MIN_DIM + 1), MIN_DIM + rng.below(MAX_DIM - MIN_DIM + 1));
+        let mut board = Board::with_rules(width, height, rules);
+        let mut color = PlayerColor::White;
+        for _ in 0..MAX_VERIFY_PLIES {
+            positions += 1;
+            for (r, c) in (0..height).flat_map(|r| (0..width).map(move |c| (r, c))) {
+                let Some(piece) = board.grid[r][c] else { continue; };
+                let reference = by_square(board.reference_valid_moves(r, c, piece));
+                board.fill_valid_moves(r, c, piece, &mut buffer);
+                for (generator, moves) in [("calculate_valid_moves", board.calculate_valid_moves(r, c, piece)), ("fill_valid_moves", buffer.clone())] {
+                    if by_square(moves.clone()) != reference {
+                        eprintln!("{} disagrees with the reference for the {} on {} in {} ({}):", generator, piece,
+                            coords_to_algebraic(r, c, height), board.to_notation(), rules.to_tag());
+                        eprintln!("  got       {:?}", moves);
+                        eprintln!("  reference {:?}", reference);
+                        return 1;
+                    }
+                }
+            }
+            let moves = board.legal_moves(color);
+            if moves.is_empty() { break; }
+            let (from, m) = moves[rng.below(moves.len())];
+            if board.apply_move(from.0, from.1, &m).is_some_and(|p| p.piece_type == PieceType::ProductOwner) { break; }
+            color = color.opponent();
+        }
+    }
+    outln!("Move generators agree in {} positions from {} games (seed {}).", positions, games, seed);
+    0
 }
 
-fn algebraic_to_coords(s: &str, board_height: usize, board_width: usize) -> Result<(usize, usize), String> {
-    if s.len() < 2 { return Err(format!("Invalid coordinate format: {}", s)); }
-    let mut chars = s.chars();
-    let col_char = chars.next().unwrap().to_ascii_uppercase();
-    let row_str: String = chars.collect();
-    let col_idx = (col_char as u8).wrapping_sub(b'A') as usize;
-    let row_num = match row_str.parse::<usize>() {
-        Ok(n) => n,
-        Err(_) => return Err(format!("Invalid row number in coordinate: {}", s)),
-    };
-    if row_num == 0 || row_num > board_height { return Err(format!("Row number {} out of bounds (1-{}).", row_num, board_height)); }
-    let row_idx = row_num - 1;
-    if col_idx >= board_width { return Err(format!("Column {} out of bounds (A-{}).", col_char, (b'A' + board_width as u8 - 1) as char)); }
-    Ok((row_idx, col_idx))
-}
+// Command dispatch, shared by the interactive game and programs embedding the engine
+impl GameState {
+    // Runs one command line such as "move B1 C3", printing what it has to say. Unknown commands, and
+    // commands other than AFTER_GAME_COMMANDS once the game is over, are returned as errors.
+    fn run_command(&mut self, input: &str) -> Result<(), String> {
+        let parts: Vec<&str> = input.split_whitespace().collect();
+        let Some(command) = parts.first().map(|s| s.to_lowercase()) else { return Ok(()); };
+        if self.settings.echo { outln!("(interpreted: {})", interpret_command(&command, &parts[1..])); }
+
+        if self.game_over && !AFTER_GAME_COMMANDS.contains(&command.as_str()) {
+            return Err("Game is over. Type \"restart\" to play again or \"exit\" to leave.".to_string());
+        }
+        if self.setup_backup.is_some() { return self.run_setup_command(&command, &parts); }
+        if !command_inventory().iter().any(|(words, _, _)| words.split(' ').next() == Some(command.as_str())) {
+            return Err(tr("error.unknown_command", &[("command", &command)]));
+        }
+
+        // With lockselection, a selected piece has to be moved, swapped for another or deselected first
+        if let Some((r, c)) = self.selected_square_coords.filter(|_| self.settings.lock_selection && !self.game_over) {
+            let moves_other_piece = command == "move" && parse_move_args(&parts[1..])
+                .is_some_and(|(from, _, _)| algebraic_to_coords(from, self.board.height, self.board.width) != Ok((r, c)));
+            if !LOCKED_SELECTION_COMMANDS.contains(&command.as_str()) || moves_other_piece {
+                return Err(format!("A piece is selected on {}: move it, select another piece, or type \"deselect\".",
+                    coords_to_algebraic(r, c, self.board.height)));
+            }
+        }
+
+        match command.as_str() {
+            "help" => print_help(),
+            "exit" => { outln!("Exiting Unvoid Chess. Goodbye!"); self.exit_requested = true; }
+            "tutorial" => {
+                // The tutorial ends with a fresh game, so don't throw away one in progress without asking
+                if !self.move_history.is_empty() && !confirm("The tutorial ends the game in progress. Proceed?") {
+                    outln!("Tutorial cancelled.");
+                } else {
+                    if !run_tutorial() { outln!("Leaving the tutorial."); }
+                    self.restart((self.board.width, self.board.height));
+                }
+            }
+            "restart" => {
+                // Keep the size of the board being played rather than the launch size, which
+                // "restart default" goes back to
+                match parts.get(1).map(|s| s.to_lowercase()) {
+                    None => self.restart((self.board.width, self.board.height)),
+                    Some(s) if parts.len() == 2 && s == "default" => self.restart(self.launch_size),
+                    _ => outln!("Invalid input: Usage: restart [default]"),
+                }
+            }
+            "name" => {
+                // Free-text command: everything after the color is the name
+                let args = split_args(input, 3);
+                let color = match args.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if s == "white" => Some(PlayerColor::White),
+                    Some(s) if s == "black" => Some(PlayerColor::Black),
+                    _ => None,
+                };
+                match (color, args.get(2)) {
+                    (Some(color), Some(name)) if !name.trim().is_empty() => {
+                        let name = name.trim().to_string();
+                        outln!("{:?} is now {}.", color, name);
+                        match color {
+                            PlayerColor::White => self.white_name = Some(name),
+                            PlayerColor::Black => self.black_name = Some(name),
+                        }
+                    }
+                    _ => {
+                        outln!("Invalid input: The 'name' command takes a color and a name.");
+                        outln!("Usage: name <white|black> <name>");
+                        outln!("Example: name white \"The Grandmaster\"");
+                    }
+                }
+            }
+            "analysis" => { apply_toggle(&parts, "Analysis mode", &mut self.analysis); }
+            "setup" => match parts.get(1).map(|s| s.to_lowercase()) {
+                Some(s) if parts.len() > 2 || s != "empty" => outln!("Usage: setup [empty]"),
+                start => {
+                    self.setup_backup = Some(self.board.clone());
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                    if start.is_some() { self.board.clear(); }
+                    outln!("Setup mode: edit the board, then type \"done\" to play from it or \"cancel\" to go back.");
+                }
+            },
+            "turn" => {
+                let side = match parts.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 2 && s == "white" => Some(PlayerColor::White),
+                    Some(s) if parts.len() == 2 && s == "black" => Some(PlayerColor::Black),
+                    _ => None,
+                };
+                match side {
+                    _ if !self.analysis => outln!("Invalid input: 'turn' is only available in analysis mode (type \"analysis on\")."),
+                    _ if self.game_over => outln!("The game is over."),
+                    Some(color) => {
+                        self.current_player = color;
+                        // Before the first move this just changes who starts; after it the moves no longer replay
+                        if self.move_history.is_empty() {
+                            self.start_position = Some(position_with_side(&self.board, color));
+                        } else {
+                            if !self.edited && self.autosave { outln!("Autosave paused: an edited game can't be rebuilt from its moves."); }
+                            self.edited = true;
+                        }
+                        self.selected_square_coords = None;
+                        self.available_moves_for_selected = None;
+                        self.auto_checked_at = None;
+                        outln!("{:?} to move.", color);
+                    }
+                    None => outln!("Usage: turn <white|black>"),
+                }
+            }
+            "size" => {
+                let board = &self.board;
+                outln!("Size: {}x{} (width x height)", board.width, board.height);
+                outln!("Squares: {} to {}", coords_to_algebraic(0, 0, board.height), coords_to_algebraic(board.height - 1, board.width - 1, board.height));
+            }
+            "branching" => self.display_branching(),
+            "longest" => self.display_longest_move(),
+            "stuck" => self.display_stuck_pieces(),
+            "hint" => self.display_hint(),
+            "transcript" => out!("{}", self.to_transcript()),
+            "id" => outln!("Game id: {}", self.id),
+            "autosave" => {
+                // "autosave <file>" starts saving there; on/off pause and resume saving to the same file
+                let args = split_args(input, 2);
+                match args.get(1).map(|s| s.as_str()) {
+                    None => match &self.autosave_file {
+                        Some(file) => outln!("Autosave: {} ({})", on_off(self.autosave), file),
+                        None => outln!("Autosave: off. Usage: autosave <file|on|off>"),
+                    },
+                    Some("off") => { self.autosave = false; outln!("Autosave: off"); }
+                    Some("on") if self.autosave_file.is_none() => outln!("Invalid input: Name a file first, e.g. autosave game.txt"),
+                    Some(_) if self.edited => outln!("Invalid input: This game was edited mid-game (turn), so it can't be rebuilt from its moves. Restart to autosave."),
+                    Some(arg) => {
+                        if arg != "on" { self.autosave_file = Some(arg.to_string()); }
+                        self.autosave = true;
+                        outln!("Autosave: on ({})", self.autosave_file.as_deref().unwrap_or_default());
+                        self.write_autosave();
+                    }
+                }
+            }
+            "frames" => {
+                // Without a file name, the game's id names the file
+                let args = split_args(input, 2);
+                let file = args.get(1).cloned().unwrap_or_else(|| format!("unvoid-{}-frames.txt", self.id));
+                match std::fs::write(&file, self.render_frames()) {
+                    Ok(()) => outln!("Wrote {} frames to {}.", self.move_history.len() + 1, file),
+                    Err(e) => outln!("Could not write {}: {}", file, e),
+                }
+            }
+            "csv" => {
+                let args = split_args(input, 2);
+                match args.get(1) {
+                    Some(file) => match std::fs::write(file, self.board.to_csv()) {
+                        Ok(()) => outln!("Wrote the board to {}.", file),
+                        Err(e) => outln!("Could not write {}: {}", file, e),
+                    },
+                    None => outln!("Usage: csv <file>"),
+                }
+            }
+            "comment" => {
+                // Free-text command: everything after "comment" is the text
+                let args = split_args(input, 2);
+                match args.get(1) {
+                    Some(text) => match self.comment_last_move(text) {
+                        Ok(()) => outln!("Comment added to move {}.", self.move_history.len()),
+                        Err(e) => outln!("{}", e),
+                    },
+                    None => outln!("Usage: comment <text>"),
+                }
+            }
+            "peek" => self.peek_next = true,
+            "defense" => self.show_defense = true,
+            "coverage" => self.show_coverage = true,
+            "heatmap" => match parts.get(1).map(|s| parse_color(s)) {
+                None => self.display_heatmap(self.current_player),
+                Some(Some(color)) if parts.len() == 2 => self.display_heatmap(color),
+                _ => outln!("Usage: heatmap [white|black]"),
+            },
+            "wincaptures" => {
+                let arg = parts.get(1).map(|s| s.to_lowercase());
+                match arg.as_deref() {
+                    Some("off") if parts.len() == 2 => {
+                        self.board.rules.capture_target = None;
+                        outln!("Capture-count win: off (capture the ProductOwner to win)");
+                    }
+                    Some(n) if parts.len() == 2 && n.parse::<usize>().is_ok_and(|n| n > 0) => {
+                        let target = n.parse::<usize>().unwrap();
+                        self.board.rules.capture_target = Some(target);
+                        outln!("Capture-count win: first to {} captures (or the ProductOwner) wins", target);
+                    }
+                    _ => {
+                        match self.board.rules.capture_target {
+                            Some(target) => outln!("Capture-count win: {}", target),
+                            None => outln!("Capture-count win: off"),
+                        }
+                        outln!("Usage: wincaptures <n|off>");
+                    }
+                }
+            }
+            "variants" => display_variants(self.board.rules),
+            "suddendeath" => {
+                let mut sudden_death = !self.board.rules.play_on;
+                if apply_toggle(&parts, "Sudden death", &mut sudden_death) {
+                    self.board.rules.play_on = !sudden_death;
+                    if !sudden_death { outln!("Capturing a ProductOwner no longer ends the game; take every enemy piece to win."); }
+                }
+            }
+            "draw" => {
+                let offer = format!("{}, {} offers a draw. Accept?", self.player_label(self.current_player.opponent()),
+                    self.player_label(self.current_player));
+                let accepted = confirm(&offer);
+                self.events.push(GameEvent::DrawOffered { by: self.current_player, accepted });
+                if accepted {
+                    self.game_over = true;
+                    self.events.push(GameEvent::GameEnded { result: GameResult::Draw, reason: EndReason::Agreement });
+                } else {
+                    outln!("Draw declined. {} to move.", self.player_label(self.current_player));
+                }
+            }
+            "takeback" => {
+                // Like a draw offer, the player who made the last move asks and the opponent answers
+                let Some(last) = self.move_history.last().filter(|_| self.takeback_position.is_some()) else {
+                    outln!("There is no move to take back.");
+                    return Ok(());
+                };
+                if self.ai_player.is_some() {
+                    outln!("Takebacks are agreed between two players; the computer doesn't take moves back.");
+                    return Ok(());
+                }
+                let request = format!("{}, {} asks to take back {}. Accept?", self.player_label(last.player.opponent()),
+                    self.player_label(last.player), last.notation(self.board.height));
+                if confirm(&request) {
+                    let notation = last.notation(self.board.height);
+                    self.take_back();
+                    outln!("Took back {}. {} to move.", notation, self.player_label(self.current_player));
+                } else {
+                    outln!("Takeback declined. {} to move.", self.player_label(self.current_player));
+                }
+            }
+            "noprogress" => {
+                let arg = parts.get(1).map(|s| s.to_lowercase());
+                match arg.as_deref() {
+                    Some("off") if parts.len() == 2 => {
+                        self.board.rules.no_progress_limit = None;
+                        outln!("No-progress draw: off");
+                    }
+                    Some(n) if parts.len() == 2 && n.parse::<usize>().is_ok_and(|n| n > 0) => {
+                        let limit = n.parse::<usize>().unwrap();
+                        self.board.rules.no_progress_limit = Some(limit);
+                        outln!("No-progress draw: after {} plies in a row without a capture", limit);
+                    }
+                    _ => {
+                        match self.board.rules.no_progress_limit {
+                            Some(limit) => outln!("No-progress draw: {}", limit),
+                            None => outln!("No-progress draw: off"),
+                        }
+                        outln!("Usage: noprogress <n|off>");
+                    }
+                }
+            }
+            "progress" if parts.len() == 1 => self.display_progress(),
+            "progress" => { apply_toggle(&parts, "Progress in turn info", &mut self.settings.show_progress); }
+            "handicap" => {
+                let handicap = match (parts.get(1), parts.get(2)) {
+                    (Some(s), None) if s.eq_ignore_ascii_case("off") => Some(None),
+                    (Some(color), Some(piece)) if parts.len() == 3 => parse_color(color).zip(parse_extra_piece(piece)).map(Some),
+                    _ => None,
+                };
+                match handicap {
+                    Some(_) if !self.move_history.is_empty() => {
+                        outln!("Invalid input: The handicap can only be changed before the first move.");
+                    }
+                    Some(handicap) => {
+                        let previous = self.board.rules.handicap;
+                        self.board.rules.handicap = handicap;
+                        self.board.setup_pieces();
+                        self.selected_square_coords = None;
+                        self.available_moves_for_selected = None;
+                        let placed = handicap.is_none_or(|(color, piece_type)| self.board.count_pieces(color, piece_type) == 2);
+                        let one_owner_each = [PlayerColor::White, PlayerColor::Black].iter()
+                            .all(|color| self.board.count_pieces(*color, PieceType::ProductOwner) == 1);
+                        match handicap {
+                            _ if !placed || !one_owner_each => {
+                                outln!("Invalid input: There is no free square for the extra piece on that back rank.");
+                                self.board.rules.handicap = previous;
+                                self.board.setup_pieces();
+                            }
+                            Some((color, piece_type)) => outln!("Handicap: {} starts with an extra {:?}.", self.player_label(color), piece_type),
+                            None => outln!("Handicap: off"),
+                        }
+                    }
+                    None => {
+                        match self.board.rules.handicap {
+                            Some((color, piece_type)) => outln!("Handicap: {} has an extra {:?}", self.player_label(color), piece_type),
+                            None => outln!("Handicap: off"),
+                        }
+                        outln!("Usage: handicap <white|black> <developer|designer> (or handicap off)");
+                    }
+                }
+            }
+            "closesetup" => {
+                let height = self.board.height;
+                let gap = match parts.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 2 && s == "off" => Some(None),
+                    Some(s) if parts.len() == 2 => s.parse::<usize>().ok().filter(|n| (1..height).contains(n)).map(Some),
+                    _ => None,
+                };
+                match gap {
+                    Some(_) if !self.move_history.is_empty() => {
+                        outln!("Invalid input: The starting ranks can only be changed before the first move.");
+                    }
+                    Some(gap) => {
+                        self.board.rules.rank_gap = gap;
+                        self.board.setup_pieces();
+                        self.selected_square_coords = None;
+                        self.available_moves_for_selected = None;
+                        match gap {
+                            Some(gap) => outln!("Back ranks start {} rows apart (rows 1 and {}).", gap, gap + 1),
+                            None => outln!("Back ranks start on the board edges."),
+                        }
+                    }
+                    None => {
+                        match self.board.rules.rank_gap {
+                            Some(gap) => outln!("Back rank gap: {} rows", gap),
+                            None => outln!("Back rank gap: off (the board edges)"),
+                        }
+                        outln!("Usage: closesetup <1-{}|off>", height - 1);
+                    }
+                }
+            }
+            "corners" => {
+                let layout = match parts.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 2 && s == "standard" => Some(SetupLayout::Standard),
+                    Some(s) if parts.len() == 2 && s == "mirrored" => Some(SetupLayout::Mirrored),
+                    Some(s) if parts.len() == 2 && s == "sameside" => Some(SetupLayout::SameSide),
+                    Some(s) if parts.len() == 2 && s == "random" => {
+                        Some([SetupLayout::Standard, SetupLayout::Mirrored, SetupLayout::SameSide][self.rng.below(3)])
+                    }
+                    _ => None,
+                };
+                match layout {
+                    Some(_) if !self.move_history.is_empty() => {
+                        outln!("Invalid input: The starting corners can only be changed before the first move.");
+                    }
+                    Some(layout) => {
+                        self.board.rules.setup_layout = layout;
+                        self.board.setup_pieces();
+                        self.selected_square_coords = None;
+                        self.available_moves_for_selected = None;
+                        // Each side must still start with exactly one ProductOwner
+                        let valid = [PlayerColor::White, PlayerColor::Black].iter()
+                            .all(|color| self.board.count_pieces(*color, PieceType::ProductOwner) == 1);
+                        if valid {
+                            outln!("Starting corners: {:?}", layout);
+                        } else {
+                            outln!("Layout {:?} does not fit this board, reverting to Standard.", layout);
+                            self.board.rules.setup_layout = SetupLayout::Standard;
+                            self.board.setup_pieces();
+                        }
+                    }
+                    None => {
+                        outln!("Starting corners: {:?}", self.board.rules.setup_layout);
+                        outln!("Usage: corners <standard|mirrored|sameside|random>");
+                    }
+                }
+            }
+            "mistakes" => self.display_mistakes(),
+            "events" => {
+                if self.events().is_empty() { outln!("Nothing has happened yet."); }
+                for (i, event) in self.events().iter().enumerate() {
+                    outln!("{:>3}. {}", i + 1, event.describe(self.board.height));
+                }
+            }
+            "bench" => {
+                let depth = parts.get(1).map(|s| s.parse::<u32>().ok().filter(|d| (1..=MAX_BENCH_DEPTH).contains(d)));
+                let iterations = parts.get(2).map(|s| s.parse::<u32>().ok().filter(|n| (1..=100).contains(n)));
+                match (depth, iterations) {
+                    (Some(None), _) | (_, Some(None)) => outln!("Usage: bench [depth 1-{}] [iterations 1-100]", MAX_BENCH_DEPTH),
+                    _ if parts.len() > 3 => outln!("Usage: bench [depth 1-{}] [iterations 1-100]", MAX_BENCH_DEPTH),
+                    (depth, iterations) => run_benchmark(self.board.width, self.board.height,
+                        depth.flatten().unwrap_or(4), iterations.flatten().unwrap_or(3)),
+                }
+            }
+            "perftdivide" => {
+                match parts.get(1).and_then(|s| s.parse::<u32>().ok()) {
+                    Some(depth) if parts.len() == 2 && (1..=MAX_BENCH_DEPTH).contains(&depth) => {
+                        let board = &self.board;
+                        let divide = perft_divide(board, self.current_player, depth);
+                        for ((from, m), nodes) in &divide {
+                            outln!("  {}{}{}: {}", coords_to_algebraic(from.0, from.1, board.height),
+                                if m.is_capture { 'x' } else { '-' }, coords_to_algebraic(m.to_r, m.to_c, board.height), nodes);
+                        }
+                        outln!("Moves: {}, nodes: {}", divide.len(), divide.iter().map(|(_, nodes)| nodes).sum::<u64>());
+                    }
+                    _ => outln!("Usage: perftdivide <depth 1-{}>", MAX_BENCH_DEPTH),
+                }
+            }
+            "validate" => {
+                let problems = self.validate();
+                if problems.is_empty() {
+                    outln!("Position OK: no problems found.");
+                } else {
+                    outln!("Found {} problem(s):", problems.len());
+                    for problem in problems { outln!("  - {}", problem); }
+                }
+            }
+            "ai" if parts.get(1).is_some_and(|s| s.eq_ignore_ascii_case("depth")) => {
+                match parts.get(2).and_then(|s| s.parse::<u32>().ok()) {
+                    Some(depth) if parts.len() == 3 && (1..=MAX_AI_DEPTH).contains(&depth) => {
+                        self.ai_config.depth = depth;
+                        outln!("AI search depth: {} plies", depth);
+                    }
+                    _ => {
+                        outln!("AI search depth: {} plies", self.ai_config.depth);
+                        outln!("Usage: ai depth <1-{}>", MAX_AI_DEPTH);
+                    }
+                }
+            }
+            "ai" if parts.get(1).is_some_and(|s| s.eq_ignore_ascii_case("table")) => {
+                match parts.get(2).and_then(|s| parse_toggle(s)) {
+                    Some(value) if parts.len() == 3 => {
+                        self.ai_config.use_table = value;
+                        outln!("Transposition table: {}", on_off(value));
+                    }
+                    _ => {
+                        outln!("Transposition table: {}", on_off(self.ai_config.use_table));
+                        outln!("Usage: ai table <on|off>");
+                    }
+                }
+            }
+            "compare" => {
+                let games = parts.get(1).and_then(|s| s.parse::<usize>().ok()).filter(|n| (1..=100).contains(n));
+                let configs = parts.get(2).zip(parts.get(3))
+                    .map(|(a, b)| parse_ai_config(a, self.ai_config).and_then(|a| Ok((a, parse_ai_config(b, self.ai_config)?))));
+                match (games, configs) {
+                    (Some(games), Some(Ok((a, b)))) if parts.len() == 4 => self.run_engine_match(games, a, b),
+                    (_, Some(Err(e))) => outln!("{}", e),
+                    _ => outln!("Usage: compare <games 1-100> <config A> <config B> (e.g. compare 10 depth=2 depth=3)"),
+                }
+            }
+            "ai" if parts.get(1).is_some_and(|s| s.eq_ignore_ascii_case("weight")) => {
+                let weights = &mut self.ai_config.weights;
+                let term = match parts.get(2).map(|s| s.to_lowercase()).as_deref() {
+                    Some("material") => Some(&mut weights.material),
+                    Some("mobility") => Some(&mut weights.mobility),
+                    Some("center") => Some(&mut weights.center),
+                    Some("safety") => Some(&mut weights.owner_safety),
+                    _ => None,
+                };
+                match (term, parts.get(3).and_then(|s| s.parse::<i32>().ok())) {
+                    (Some(term), Some(value)) if parts.len() == 4 && (0..=100).contains(&value) => {
+                        *term = value;
+                        outln!("AI weight for {}: {}", parts[2].to_lowercase(), value);
+                    }
+                    _ => {
+                        outln!("AI weights: material {}, mobility {}, center {}, safety {}", weights.material, weights.mobility,
+                            weights.center, weights.owner_safety);
+                        outln!("Usage: ai weight <material|mobility|center|safety> <0-100>");
+                    }
+                }
+            }
+            "eval" => self.display_eval(),
+            "material" => outln!("{}", self.board.material_line()),
+            "ai" if parts.len() == 2 && parts[1].eq_ignore_ascii_case("stats") => {
+                match self.last_search {
+                    Some(stats) => {
+                        outln!("Last AI move: depth {} reached, {} nodes searched", stats.depth_reached, stats.nodes);
+                        if let Some(reason) = stats.reason { outln!("Reason: {}", reason); }
+                        if stats.table_probes > 0 {
+                            outln!("Transposition table: {} hits / {} probes ({:.1}%)", stats.table_hits, stats.table_probes,
+                                100.0 * stats.table_hits as f64 / stats.table_probes as f64);
+                        } else {
+                            outln!("Transposition table: not used");
+                        }
+                    }
+                    None => outln!("The computer hasn't moved yet."),
+                }
+            }
+            "ai" => {
+                let side = match parts.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 2 && s == "white" => Some(Some(PlayerColor::White)),
+                    Some(s) if parts.len() == 2 && s == "black" => Some(Some(PlayerColor::Black)),
+                    Some(s) if parts.len() == 2 && s == "off" => Some(None),
+                    _ => None,
+                };
+                match side {
+                    Some(Some(color)) => { self.ai_player = Some(color); outln!("The computer now plays {:?}.", color); }
+                    Some(None) => { self.ai_player = None; outln!("The computer no longer plays."); }
+                    None => outln!("Usage: ai <white|black|off>"),
+                }
+            }
+            "aitime" | "movetime" => {
+                match parts.get(1).and_then(|s| s.parse::<u64>().ok()) {
+                    Some(ms) if parts.len() == 2 && ms > 0 => {
+                        self.ai_config.time_budget_ms = ms;
+                        outln!("AI time budget: {} ms per move", ms);
+                    }
+                    _ => {
+                        outln!("AI time budget: {} ms per move", self.ai_config.time_budget_ms);
+                        outln!("Usage: {} <milliseconds>", command);
+                    }
+                }
+            }
+            "scoring" => { apply_toggle(&parts, "Tournament scoring", &mut self.settings.tournament_scoring); }
+            "score" => self.display_score(),
+            "winalert" => { apply_toggle(&parts, "Win alert", &mut self.settings.win_alert); }
+            "verbose" => { apply_toggle(&parts, "Verbose", &mut self.settings.verbose); }
+            "echo" => { apply_toggle(&parts, "Echo commands", &mut self.settings.echo); }
+            "attackers" => { apply_toggle(&parts, "Attackers on selection", &mut self.settings.show_attackers); }
+            "warnhang" => { apply_toggle(&parts, "Hanging ProductOwner warning", &mut self.settings.warn_hanging); }
+            "auto" => {
+                if apply_toggle(&parts, "Auto-select", &mut self.settings.auto_select) {
+                    self.auto_checked_at = None;
+                }
+            }
+            "color" => { apply_toggle(&parts, "Color", &mut self.display_options.color); }
+            "checker" => {
+                if apply_toggle(&parts, "Checkerboard shading", &mut self.display_options.checker)
+                    && self.display_options.checker && !self.display_options.color {
+                    outln!("Note: shading is only drawn with color mode on (type \"color on\").");
+                }
+            }
+            "coordpad" => {
+                if apply_toggle(&parts, "Zero-padded coordinates", &mut self.display_options.coord_pad) { self.display_options.make_active(); }
+            }
+            "zeroranks" => {
+                if apply_toggle(&parts, "0-based ranks", &mut self.display_options.zero_ranks) { self.display_options.make_active(); }
+            }
+            "theme" => {
+                let theme = match parts.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 2 && s == "unicode" => Some(Theme::Unicode),
+                    Some(s) if parts.len() == 2 && s == "emoji" => Some(Theme::Emoji),
+                    Some(s) if parts.len() == 2 && s == "ascii" => Some(Theme::Ascii),
+                    _ => None,
+                };
+                match theme {
+                    Some(theme) => {
+                        self.display_options.theme = theme;
+                        self.display_options.make_active();
+                        outln!("Theme: {:?}", theme);
+                        if theme != Theme::Ascii && !terminal_supports_unicode() {
+                            outln!("Note: no UTF-8 locale found, so these symbols may not show. \"theme ascii\" works everywhere.");
+                        }
+                    }
+                    None => {
+                        outln!("Theme: {:?}", self.display_options.theme);
+                        outln!("Usage: theme <unicode|emoji|ascii>");
+                    }
+                }
+            }
+            "compact" => { apply_toggle(&parts, "Compact display", &mut self.display_options.compact); }
+            "ranktop" => { apply_toggle(&parts, "Rank 1 at the top", &mut self.display_options.rank_one_top); }
+            "border" => {
+                let style = match parts.get(1).map(|s| s.to_lowercase()).as_deref() {
+                    Some("ascii") if parts.len() == 2 => Some(BorderStyle::Ascii),
+                    Some("unicode") if parts.len() == 2 => Some(BorderStyle::Unicode),
+                    Some("none") if parts.len() == 2 => Some(BorderStyle::None),
+                    _ => None,
+                };
+                match style {
+                    Some(style) => { self.display_options.border = style; outln!("Border: {:?}", style); }
+                    None => {
+                        outln!("Border: {:?}", self.display_options.border);
+                        outln!("Usage: border <ascii|unicode|none>");
+                    }
+                }
+            }
+            "labels" => {
+                // First/last is top/bottom for the file letters and left/right for the rank numbers
+                let options = &mut self.display_options;
+                let (edges, setting) = match parts.get(1).map(|s| s.to_lowercase()).as_deref() {
+                    Some("files") => (("top", "bottom"), Some(&mut options.file_labels)),
+                    Some("ranks") => (("left", "right"), Some(&mut options.rank_labels)),
+                    _ => (("", ""), None),
+                };
+                let placement = match parts.get(2).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 3 && s == edges.0 => Some((true, false)),
+                    Some(s) if parts.len() == 3 && s == edges.1 => Some((false, true)),
+                    Some(s) if parts.len() == 3 && s == "both" => Some((true, true)),
+                    Some(s) if parts.len() == 3 && s == "none" => Some((false, false)),
+                    _ => None,
+                };
+                match (setting, placement) {
+                    (Some(setting), Some(placement)) => *setting = placement,
+                    _ if parts.len() == 1 => {}
+                    _ => outln!("Usage: labels files <top|bottom|both|none> or labels ranks <left|right|both|none>"),
+                }
+                let describe = |(first, last): (bool, bool), names: (&str, &str)| match (first, last) {
+                    (true, true) => "both".to_string(),
+                    (false, false) => "none".to_string(),
+                    _ => (if first { names.0 } else { names.1 }).to_string(),
+                };
+                outln!("Labels: files {}, ranks {}", describe(options.file_labels, ("top", "bottom")), describe(options.rank_labels, ("left", "right")));
+            }
+            "marker" => {
+                let options = &mut self.display_options;
+                let result = match (parts.get(1).map(|s| s.to_lowercase()), parts.get(2)) {
+                    (Some(kind), Some(value)) if parts.len() == 3 && kind == "quiet" => parse_marker(value).map(|ch| options.quiet_marker = ch),
+                    (Some(kind), Some(value)) if parts.len() == 3 && kind == "capture" => parse_marker(value).map(|ch| options.capture_marker = ch),
+                    (Some(kind), Some(value)) if parts.len() == 3 && kind == "select" => {
+                        let mut chars = value.chars();
+                        match (chars.next(), chars.next(), chars.next()) {
+                            (Some(open), Some(close), None) => parse_marker(&open.to_string())
+                                .and_then(|open| parse_marker(&close.to_string()).map(|close| options.select_brackets = (open, close))),
+                            _ => Err("Invalid input: The select marker takes two characters, e.g. marker select <>".to_string()),
+                        }
+                    }
+                    _ => Err("Usage: marker <quiet|capture|select> <chars>\nExample: marker capture *".to_string()),
+                };
+                match result {
+                    Ok(()) => outln!("Markers: quiet '{}', capture '{}', select '{}{}'", options.quiet_marker, options.capture_marker,
+                        options.select_brackets.0, options.select_brackets.1),
+                    Err(e) => outln!("{}", e),
+                }
+            }
+            "reach" => {
+                if parts.len() == 2 {
+                    match algebraic_to_coords(parts[1], self.board.height, self.board.width) {
+                        Ok((r, c)) => {
+                            if let Err(e) = self.display_reach(r, c) { outln!("{}", e); }
+                        }
+                        Err(_) => outln!("Invalid input: {} is not a valid square on the board.", parts[1].to_uppercase()),
+                    }
+                } else {
+                    outln!("Usage: reach <square>");
+                    outln!("Example: reach B1");
+                }
+            }
+            "tree" => {
+                match parts.get(1).map(|sq| (sq, algebraic_to_coords(sq, self.board.height, self.board.width))) {
+                    Some((_, Ok((r, c)))) if parts.len() == 2 => {
+                        if let Err(e) = self.display_tree(r, c) { outln!("{}", e); }
+                    }
+                    Some((sq, Err(_))) if parts.len() == 2 => outln!("Invalid input: {} is not a valid square on the board.", sq.to_uppercase()),
+                    _ => {
+                        outln!("Usage: tree <square>");
+                        outln!("Example: tree B1");
+                    }
+                }
+            }
+            "dev_land_capture" => {
+                if apply_toggle(&parts, "Developer landing captures", &mut self.board.rules.dev_land_capture) {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                }
+            }
+            "norepeatpiece" => {
+                if apply_toggle(&parts, "No repeated piece", &mut self.board.rules.no_repeat_piece) {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                }
+            }
+            "strict" => {
+                if apply_toggle(&parts, "Strict mode", &mut self.board.rules.strict) {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                }
+            }
+            "dev_orthogonal" => {
+                if apply_toggle(&parts, "Orthogonal-only Developers", &mut self.board.rules.dev_orthogonal) {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                }
+            }
+            "mustcapture" => {
+                if apply_toggle(&parts, "Forced captures", &mut self.board.rules.must_capture) {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                }
+            }
+            "fog" => {
+                if apply_toggle(&parts, "Fog of war", &mut self.board.rules.fog_of_war) {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                }
+            }
+            "designer" => {
+                let shape = match parts.get(1).map(|s| s.to_lowercase()) {
+                    Some(s) if parts.len() == 2 && s == "standard" => Some(DesignerShape::Standard),
+                    Some(s) if parts.len() == 2 && s == "extended" => Some(DesignerShape::Extended),
+                    _ => None,
+                };
+                match shape {
+                    Some(shape) => {
+                        self.board.set_designer_shape(shape);
+                        self.selected_square_coords = None;
+                        self.available_moves_for_selected = None;
+                        outln!("Designer move shape: {:?}", shape);
+                    }
+                    None => {
+                        outln!("Designer move shape: {:?}", self.board.rules.designer_shape);
+                        outln!("Usage: designer <standard|extended>");
+                    }
+                }
+            }
+            "deselect" => {
+                if self.selected_square_coords.is_none() {
+                    outln!("Invalid input: No piece is selected.");
+                } else {
+                    self.selected_square_coords = None;
+                    self.available_moves_for_selected = None;
+                    outln!("Selection cleared.");
+                }
+            }
+            "groupmoves" => { apply_toggle(&parts, "Moves grouped by direction", &mut self.settings.group_moves); }
+            "lockselection" => { apply_toggle(&parts, "Selection lock", &mut self.settings.lock_selection); }
+            "select" => {
+                if parts.len() == 2 {
+                    let sq_str = parts[1];
+                    match algebraic_to_coords(sq_str, self.board.height, self.board.width) {
+                        Ok((r, c)) => {
+                            select_and_report(self, r, c);
+                        }
+                        Err(_) => { // Use generic error from images for bad coord format
+                             outln!("Invalid input: {} is not a valid square on the board.", sq_str.to_uppercase());
+                             outln!("Please enter coordinates from {} to {}.", coords_to_algebraic(0, 0, self.board.height),
+                                coords_to_algebraic(self.board.height - 1, self.board.width - 1, self.board.height));
+                        }
+                    }
+                } else {
+                    if parts.len() < 2 {
+                        outln!("Invalid input: Too few arguments. The 'select' command needs a square.");
+                    } else {
+                        outln!("Invalid input: Too many arguments. The 'select' command takes only one coordinate.");
+                    }
+                    outln!("Usage: select <square>");
+                    outln!("Example: select C1");
+                }
+            }
+            "preview" => preview_command(self, &parts[1..]),
+            "matein1" => self.display_mate_in_one(),
+            "path" => match parse_move_args(&parts[1..]) {
+                Some((from_str, to_str, _)) => {
+                    match (algebraic_to_coords(from_str, self.board.height, self.board.width),
+                           algebraic_to_coords(to_str, self.board.height, self.board.width)) {
+                        (Ok((from_r, from_c)), Ok((to_r, to_c))) => {
+                            if let Err(e) = self.show_developer_path(from_r, from_c, to_r, to_c) { outln!("{}", e); }
+                        }
+                        (Err(_), _) => outln!("Invalid input: {} is not a valid 'from' square.", from_str.to_uppercase()),
+                        (_, Err(_)) => outln!("Invalid input: {} is not a valid 'to' square.", to_str.to_uppercase()),
+                    }
+                }
+                None => outln!("Usage: path <from> <to>"),
+            },
+            "move" if parts.len() > 1 && parts[parts.len() - 1].eq_ignore_ascii_case("--dry") => {
+                preview_command(self, &parts[1..parts.len() - 1]);
+            }
+            "move" => {
+                let squares = parse_move_args(&parts[1..]);
+                if let Some((from_str, to_str, marked_capture)) = squares {
+                    match (algebraic_to_coords(from_str, self.board.height, self.board.width),
+                           algebraic_to_coords(to_str, self.board.height, self.board.width)) {
+                        (Ok((from_r, from_c)), Ok((to_r, to_c))) => {
+                            // An 'x' separator promises a capture; refuse quiet moves written that way
+                            let class = self.board.get_piece(from_r, from_c)
+                                .filter(|p| p.color == self.current_player)
+                                .and_then(|_| self.board.classify_move((from_r, from_c), (to_r, to_c)));
+                            let warning = if self.settings.warn_hanging { self.hanging_warning(from_r, from_c, to_r, to_c) } else { None };
+                            if let Err(e) = self.check_visible(from_r, from_c) {
+                                outln!("{}", e);
+                            } else if marked_capture && class == Some(MoveClass::Quiet) {
+                                outln!("Invalid move: {}x{} is written as a capture, but it doesn't capture anything.",
+                                    from_str.to_uppercase(), to_str.to_uppercase());
+                            } else if warning.as_deref().is_some_and(|warning| !confirm(warning)) {
+                                outln!("Move cancelled.");
+                            } else if let Err(e) = self.attempt_move(from_r, from_c, to_r, to_c) {
+                                outln!("{}", e);
+                            }
+                        }
+                        (Err(_), _) => outln!("Invalid input: {} is not a valid 'from' square.", from_str.to_uppercase()),
+                        (_, Err(_)) => outln!("Invalid input: {} is not a valid 'to' square.", to_str.to_uppercase()),
+                    }
+                } else {
+                    match parts.len() {
+                        1 | 2 => outln!("Invalid input: Too few arguments. The 'move' command requires <from> and <to> coordinates."),
+                        _ => outln!("Invalid input: Too many arguments. The 'move' command takes only <from> and <to> coordinates."),
+                    }
+                    outln!("Usage: move <from_square> <to_square>");
+                    outln!("Example: move B1 C3 (or move B1-C3, move B1xC3 for a capture)");
+                }
+            }
+            _ => return Err(tr("error.unknown_command", &[("command", &command)])),
+        }
+        Ok(())
+    }
+
+    // Commands while the setup editor is open: pieces are put on and cleared from the board directly,
+    // and "done" starts a game from the result once setup_problems finds nothing wrong with it
+    fn run_setup_command(&mut self, command: &str, parts: &[&str]) -> Result<(), String> {
+        let (height, width) = (self.board.height, self.board.width);
+        match (command, parts) {
+            ("put", [_, piece, color, square]) => {
+                let piece = parse_piece_type(piece).zip(parse_color(color)).map(|(piece_type, color)| Piece::new(piece_type, color));
+                match (piece, algebraic_to_coords(square, height, width)) {
+                    (None, _) => outln!("Invalid input: Name the piece as O, D or S and the color as white or black, e.g. put O white D4"),
+                    (_, Err(_)) => outln!("Invalid input: {} is not a valid square on the board.", square.to_uppercase()),
+                    (Some(piece), Ok((r, c))) => {
+                        self.board.set_square(r, c, Some(piece));
+                        outln!("Put {} on {}.", piece, coords_to_algebraic(r, c, height));
+                    }
+                }
+            }
+            ("clear", [_, all]) if all.eq_ignore_ascii_case("all") => {
+                self.board.clear();
+                outln!("Cleared the board.");
+            }
+            ("clear", [_, square]) => match algebraic_to_coords(square, height, width) {
+                Ok((r, c)) => match self.board.take_square(r, c) {
+                    Some(piece) => outln!("Removed {} from {}.", piece, coords_to_algebraic(r, c, height)),
+                    None => outln!("{} is already empty.", coords_to_algebraic(r, c, height)),
+                },
+                Err(_) => outln!("Invalid input: {} is not a valid square on the board.", square.to_uppercase()),
+            },
+            ("done", [_]) | ("done", [_, _]) => {
+                let Some(color) = parts.get(1).map_or(Some(self.current_player), |s| parse_color(s)) else {
+                    outln!("Usage: done [white|black]");
+                    return Ok(());
+                };
+                let problems = self.board.setup_problems(color);
+                if !problems.is_empty() {
+                    outln!("This position can't be played yet:");
+                    for problem in problems { outln!("  {}", problem); }
+                    return Ok(());
+                }
+                self.setup_backup = None;
+                self.current_player = color;
+                self.move_history.clear();
+                self.events.clear();
+                self.game_over = false;
+                self.winner = None;
+                self.summary_shown = false;
+                self.edited = false;
+                self.start_position = Some(position_with_side(&self.board, color)); // Transcripts replay the moves from here
+                self.takeback_position = None;
+                self.auto_checked_at = None;
+                outln!("Playing from the edited position with {} to move.", self.player_label(color));
+            }
+            ("cancel", [_]) => {
+                self.board = self.setup_backup.take().unwrap_or_else(|| self.board.clone());
+                outln!("Setup cancelled; the game is back as it was.");
+            }
+            ("exit", _) => { outln!("Exiting Unvoid Chess. Goodbye!"); self.exit_requested = true; }
+            ("help", _) => outln!("{}", SETUP_USAGE),
+            ("put" | "clear" | "done" | "cancel", _) => outln!("{}", SETUP_USAGE),
+            _ => return Err(format!("Invalid input: '{}' isn't available in setup mode. {}", command, SETUP_USAGE)),
+        }
+        Ok(())
+    }
 
-fn coords_to_algebraic(r: usize, c: usize, _board_height: usize) -> String {
-    format!("{}{}", (b'A' + c as u8) as char, r + 1)
-}
+    // Runs one command and returns its output as text instead of printing it, so a host program can
+    // drive the game with the same command language. Commands that ask a question (warnhang's
+    // confirmation, the tutorial) still read the answer from stdin, printing the output so far first.
+    // After the command's own output comes what show_position prints (the board, the turn line and any
+    // reply from the computer), so a host sees what a player at the terminal does.
+    fn execute_command(&mut self, line: &str) -> Result<String, String> {
+        self.captured(|game| {
+            game.run_command(line)?;
+            if !game.exit_requested {
+                outln!();
+                game.show_position();
+            }
+            Ok(())
+        })
+    }
 
-fn get_board_dimension(prompt: &str) -> usize {
-    loop {
-        print!("{}", prompt);
-        io::stdout().flush().unwrap();
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        match input.trim().parse::<usize>() {
-            Ok(val) if (MIN_DIM..=MAX_DIM).contains(&val) => return val,
-            _ => println!("Invalid input. Please enter a number between {} and {}.", MIN_DIM, MAX_DIM),
+    // The board and turn line as show_position prints them, e.g. before the first command
+    fn position_view(&mut self) -> String {
+        self.captured(|game| { game.show_position(); Ok(()) }).unwrap_or_default()
+    }
+
+    // Runs `run` with this game's display options and returns what it printed
+    fn captured(&mut self, run: impl FnOnce(&mut GameState) -> Result<(), String>) -> Result<String, String> {
+        self.display_options.make_active();
+        CAPTURED_OUTPUT.with(|captured| *captured.borrow_mut() = Some(String::new()));
+        let result = run(self);
+        let output = CAPTURED_OUTPUT.with(|captured| captured.borrow_mut().take()).unwrap_or_default();
+        result.map(|()| output)
+    }
+
+    // What the player sees before typing the next command: the board with any one-shot overlay, the
+    // turn line, and the computer's moves for as long as it is the one to play
+    fn show_position(&mut self) {
+        loop {
+            if self.settings.auto_select && !self.game_over && self.selected_square_coords.is_none()
+                && self.auto_checked_at != Some(self.move_history.len()) {
+                self.auto_checked_at = Some(self.move_history.len());
+                self.auto_select_forced_piece();
+            }
+            let marks = if self.show_defense { self.defense_marks() }
+                else if self.show_coverage { self.coverage_marks() }
+                else { BTreeMap::new() };
+            self.board.display_marked(self.selected_square_coords, &self.available_moves_for_selected,
+                self.peek_next && self.current_player == PlayerColor::White, &self.display_options, &marks, self.fog_viewer());
+            if self.show_defense {
+                let undefended = marks.values().filter(|mark| mark.0 == '!').count();
+                outln!("(x) defended, !x! undefended: {} of {} {:?} pieces undefended", undefended, marks.len(), self.current_player);
+                self.show_defense = false;
+            }
+            if self.show_coverage {
+                let shared = marks.values().filter(|mark| mark.0 == '{').count();
+                outln!("( ) reachable by one piece, {{ }} by several: {:?} covers {} of {} empty squares ({} by several pieces)",
+                    self.current_player, marks.len(), self.board.grid.iter().flatten().filter(|sq| sq.is_none()).count(), shared);
+                self.show_coverage = false;
+            }
+            if self.peek_next {
+                outln!("(Peeking from {:?}'s side)", self.current_player.opponent());
+                self.peek_next = false;
+            }
+            self.display_turn_info();
+            if !self.game_over && self.setup_backup.is_none() && self.ai_player == Some(self.current_player) {
+                if !self.play_ai_turn() {
+                    self.ai_player = None; // Hand control back to the players
+                    outln!("AI stopped.");
+                }
+                outln!();
+                continue;
+            }
+            break;
         }
     }
+
+    fn restart(&mut self, size: (usize, usize)) {
+        outln!("Restarting match on the ({} x {}) board...", size.0, size.1);
+        self.reset_position(size);
+    }
+
+    // Starts a new game of the given size. Only the position, turn, selection, history and result start over:
+    // names, variants, display and AI settings, the random generator and the launch size carry on.
+    fn reset_position(&mut self, (width, height): (usize, usize)) {
+        let rng = std::mem::replace(&mut self.rng, Rng::new(0));
+        *self = GameState {
+            board: Board::with_rules(width, height, self.board.rules),
+            display_options: self.display_options,
+            settings: self.settings,
+            ai_player: self.ai_player,
+            ai_config: self.ai_config,
+            white_name: self.white_name.take(),
+            black_name: self.black_name.take(),
+            launch_size: self.launch_size,
+            autosave_file: self.autosave_file.take(),
+            autosave: self.autosave,
+            ..GameState::start(width, height, rng)
+        };
+    }
 }
 
 fn main() {
-    println!("Welcome to Unvoid Chess!");
-    let board_width = get_board_dimension("Enter board width (6-12): ");
-    let board_height = get_board_dimension("Enter board height (6-12): ");
-    println!("Starting match on the ({} x {}) board...", board_width, board_height);
-    
-    let mut game_state = GameState::new(board_width, board_height);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--query") {
+        std::process::exit(run_query(&args));
+    }
+    // Hidden: the command inventory for shell completion, one "words arity" line per usage (e.g. "bench 0-2")
+    if args.iter().any(|a| a == "--commands") {
+        for (words, required, optional) in command_inventory() {
+            if optional == 0 { outln!("{} {}", words, required); } else { outln!("{} {}-{}", words, required, required + optional); }
+        }
+        return;
+    }
+    if args.iter().any(|a| a == "--verify") {
+        std::process::exit(run_verify(&args));
+    }
+    let seed = match args.iter().position(|a| a == "--seed").map(|i| args.get(i + 1).and_then(|s| s.parse::<u64>().ok())) {
+        Some(Some(seed)) => seed,
+        Some(None) => { eprintln!("Invalid input: --seed takes a number."); std::process::exit(1); }
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64),
+    };
+    // --resume <file> picks up an autosaved game if the file exists, and keeps autosaving to it either way
+    let resume_file = match args.iter().position(|a| a == "--resume").map(|i| args.get(i + 1)) {
+        Some(Some(file)) => Some(file.clone()),
+        Some(None) => { eprintln!("Invalid input: --resume takes a file name."); std::process::exit(1); }
+        None => None,
+    };
+    // --lang <file> replaces messages with "key = text" lines, e.g. to translate the game
+    if let Some(file) = args.iter().position(|a| a == "--lang").map(|i| args.get(i + 1)) {
+        let Some(file) = file else { eprintln!("Invalid input: --lang takes a file name."); std::process::exit(1); };
+        let mut messages = Messages::english();
+        if let Err(e) = std::fs::read_to_string(file).map_err(|e| e.to_string()).and_then(|text| messages.load_overrides(&text)) {
+            eprintln!("Could not load messages from {}: {}", file, e);
+            std::process::exit(1);
+        }
+        let _ = MESSAGES.set(messages);
+    }
+    outln!("Welcome to Unvoid Chess!");
+    outln!("Seed: {} (start with --seed {} to repeat this session)", seed, seed);
+
+    let mut game_state = match resume_file.as_ref().filter(|file| std::path::Path::new(file).exists()) {
+        Some(file) => match std::fs::read_to_string(file).map_err(|e| e.to_string()).and_then(|text| GameState::from_transcript(&text)) {
+            Ok(state) => {
+                outln!("Resumed the game saved in {} ({} moves played).", file, state.move_history.len());
+                GameState { rng: Rng::new(seed), ..state }
+            }
+            Err(e) => { eprintln!("Could not resume from {}: {}", file, e); std::process::exit(1); }
+        },
+        None => {
+            let board_width = get_board_dimension("Enter board width (6-12): ");
+            let board_height = get_board_dimension("Enter board height (6-12): ");
+            outln!("Starting match on the ({} x {}) board...", board_width, board_height);
+            GameState::start(board_width, board_height, Rng::new(seed))
+        }
+    };
+    if let Some(file) = resume_file {
+        outln!("Autosaving to {} after every move.", file);
+        game_state.autosave_file = Some(file);
+        game_state.autosave = true;
+    }
 
+    out!("{}", game_state.position_view());
     loop {
-        game_state.board.display(game_state.selected_square_coords, &game_state.available_moves_for_selected);
-        game_state.display_turn_info();
-        
         if game_state.game_over {
             // Only allow restart or exit if game is over
         } else {
-             print!("Type a command (type \"help\" for options):\n> ");
+            // Name the side to move in the prompt so hot-seat players don't move for each other
+            let player = if game_state.setup_backup.is_some() { "setup".to_string() } else { format!("{:?}", game_state.current_player).to_lowercase() };
+            let prompt = match game_state.current_player {
+                _ if !game_state.display_options.color => player,
+                PlayerColor::White => format!("{}{}{}", ANSI_WHITE_PROMPT, player, ANSI_RESET),
+                PlayerColor::Black => format!("{}{}{}", ANSI_BLACK_PROMPT, player, ANSI_RESET),
+            };
+            out!("{}\n{}> ", tr("turn.prompt", &[]), prompt);
         }
         io::stdout().flush().unwrap();
 
         let mut input = String::new();
         io::stdin().read_line(&mut input).expect("Failed to read line");
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
+        if input.trim().is_empty() { continue; }
+
+        match game_state.execute_command(&input) {
+            Ok(output) => out!("{}", output),
+            Err(e) => outln!("{}\n", e),
+        }
+        if game_state.exit_requested { break; }
+    }
+}
+
 
-        if parts.is_empty() { continue; }
-        let command = parts[0].to_lowercase();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if game_state.game_over && !["restart", "exit"].contains(&command.as_str()) {
-            println!("Game is over. Type \"restart\" to play again or \"exit\" to leave.");
-            continue;
+    // A move as (from, to) squares
+    type Ply = ((usize, usize), (usize, usize));
+
+    // A new game on the smallest board with move messages silenced
+    fn quiet_game() -> GameState {
+        GameState { quiet: true, ..GameState::new(MIN_DIM, MIN_DIM) }
+    }
+
+    // A game on a fixed position with move messages silenced
+    fn game_from(placement: &str, to_move: PlayerColor) -> GameState {
+        let board = Board::from_notation(placement).expect("test position");
+        let (width, height) = (board.width, board.height);
+        GameState { board, current_player: to_move, quiet: true, ..GameState::new(width, height) }
+    }
+
+    // Plays each (from, to) in turn, failing the test on the first rejected move
+    fn play(game: &mut GameState, moves: &[Ply]) {
+        for &(from, to) in moves {
+            if let Err(e) = game.attempt_move(from.0, from.1, to.0, to.1) { panic!("{:?}-{:?} was rejected: {}", from, to, e); }
         }
+    }
 
-        match command.as_str() {
-            "help" => { 
-                println!("Available commands:");
-                println!("  move <from> <to>    Move a piece (e.g. move B1 C3)");
-                println!("  select <square>     Highlight piece (e.g. select B1)");
-                println!("  restart             Restart the match");
-                println!("  exit                Exit the game");
-                println!("  help                Show this list");}
-            "exit" => { println!("Exiting Unvoid Chess. Goodbye!"); break; }
-            "restart" => {
-                println!("Restarting match...");
-                game_state = GameState::new(board_width, board_height);
-            }
-            "select" => {
-                if parts.len() == 2 {
-                    let sq_str = parts[1];
-                    match algebraic_to_coords(sq_str, game_state.board.height, game_state.board.width) {
-                        Ok((r, c)) => {
-                            if let Err(e) = game_state.select_piece(r, c) { println!("{}", e); }
-                        }
-                        Err(_) => { // Use generic error from images for bad coord format
-                             println!("Invalid input: {} is not a valid square on the board.", sq_str.to_uppercase());
-                             println!("Please enter coordinates from A1 to {}{}.", 
-                                (b'A' + game_state.board.width as u8 - 1) as char, game_state.board.height);
-                        }
-                    }
-                } else {
-                    println!("Invalid input: The 'select' command takes only one coordinate.");
-                    println!("Usage: select <square>");
-                    println!("Example: select C1");
+    #[test]
+    fn transcript_replays_to_the_same_game() {
+        let mut game = quiet_game();
+        play(&mut game, &[((0, 1), (3, 1)), ((5, 4), (2, 4)), ((0, 2), (2, 3))]);
+        game.comment_last_move("developing").unwrap();
+
+        let replayed = GameState::from_transcript(&game.to_transcript()).unwrap();
+        assert_eq!(replayed.board.to_notation(), game.board.to_notation());
+        let notations = |history: &[MoveRecord]| history.iter().map(|m| (m.notation(MIN_DIM), m.comment.clone())).collect::<Vec<_>>();
+        assert_eq!(notations(&replayed.move_history), notations(&game.move_history));
+        assert_eq!(replayed.current_player, game.current_player);
+    }
+
+    #[test]
+    fn incremental_hash_matches_a_fresh_one_through_moves_and_undos() {
+        let mut board = Board::new(8, 8);
+        let start = board.hash();
+        let mut undo = Vec::new();
+        let mut color = PlayerColor::White;
+        for _ in 0..12 {
+            let Some(&(from, m)) = board.legal_moves(color).iter().max_by_key(|(_, m)| (m.is_capture, m.to_r, m.to_c)) else { break; };
+            let captured_at = m.jumped_piece_coord.unwrap_or((m.to_r, m.to_c));
+            let captured = board.apply_move(from.0, from.1, &m);
+            assert_eq!(board.hash(), board.compute_hash());
+            undo.push((from, (m.to_r, m.to_c), captured_at, captured));
+            color = color.opponent();
+        }
+        while let Some((from, to, captured_at, captured)) = undo.pop() {
+            let piece = board.take_square(to.0, to.1);
+            board.set_square(from.0, from.1, piece);
+            board.set_square(captured_at.0, captured_at.1, captured);
+            assert_eq!(board.hash(), board.compute_hash());
+        }
+        assert_eq!(board.hash(), start);
+    }
+
+    // Destinations of a move list, sorted, for comparing generators
+    fn destinations(moves: Vec<MoveDetail>) -> Vec<(usize, usize)> {
+        let mut squares: Vec<(usize, usize)> = moves.iter().map(|m| (m.to_r, m.to_c)).collect();
+        squares.sort();
+        squares
+    }
+
+    #[test]
+    fn move_tables_match_on_the_fly_moves() {
+        for shape in [DesignerShape::Standard, DesignerShape::Extended] {
+            let mut board = Board::empty(7, 9);
+            board.set_designer_shape(shape);
+            assert_eq!(*board.move_tables, MoveTables::new(7, 9, shape));
+            for square in [(0, 0), (0, 3), (4, 6), (4, 3), (8, 6), (7, 1)] {
+                for piece_type in [PieceType::ProductOwner, PieceType::Designer] {
+                    let piece = Piece::new(piece_type, PlayerColor::White);
+                    assert_eq!(destinations(board.calculate_valid_moves(square.0, square.1, piece)),
+                        destinations(board.reference_valid_moves(square.0, square.1, piece)), "{:?} {:?} on {:?}", shape, piece_type, square);
                 }
             }
-            "move" => {
-                if parts.len() == 3 {
-                    let from_str = parts[1];
-                    let to_str = parts[2];
-                    match (algebraic_to_coords(from_str, game_state.board.height, game_state.board.width),
-                           algebraic_to_coords(to_str, game_state.board.height, game_state.board.width)) {
-                        (Ok((from_r, from_c)), Ok((to_r, to_c))) => {
-                            if let Err(e) = game_state.attempt_move(from_r, from_c, to_r, to_c) {
-                                println!("{}", e);
-                            }
-                        }
-                        (Err(_), _) => println!("Invalid input: {} is not a valid 'from' square.", from_str.to_uppercase()),
-                        (_, Err(_)) => println!("Invalid input: {} is not a valid 'to' square.", to_str.to_uppercase()),
-                    }
-                } else {
-                    println!("Invalid input: The 'move' command requires <from> and <to> coordinates.");
-                    println!("Usage: move <from_square> <to_square>");
-                    println!("Example: move B1 C3");
-                }
+        }
+    }
+
+    #[test]
+    fn move_cache_matches_fresh_moves_and_is_dropped_after_a_move() {
+        let mut game = quiet_game();
+        let developer = game.board.get_piece(0, 1).unwrap();
+        let cached = game.legal_moves_from(0, 1, developer);
+        assert_eq!(game.legal_moves_from(0, 1, developer), cached);
+        assert_eq!(cached, game.compute_legal_moves_from(0, 1, developer));
+
+        // The Designer on D3 now blocks the Developer's diagonal, so the cached list is out of date
+        play(&mut game, &[((0, 2), (2, 3)), ((5, 5), (4, 5))]);
+        let fresh = game.legal_moves_from(0, 1, developer);
+        assert_eq!(fresh, game.compute_legal_moves_from(0, 1, developer));
+        assert_ne!(fresh, cached);
+    }
+
+    // Every rank of the drawing must be exactly as wide as the border around it, and each file letter
+    // must sit over its column, whatever the border style and label placement
+    #[test]
+    fn board_drawing_lines_up_on_a_12x12_board() {
+        let board = Board::new(MAX_DIM, MAX_DIM);
+        let label_placements = [(true, false), (false, true), (true, true), (false, false)];
+        for (compact, border, rank_labels) in [false, true].into_iter()
+            .flat_map(|compact| [BorderStyle::Ascii, BorderStyle::Unicode, BorderStyle::None].into_iter().map(move |border| (compact, border)))
+            .flat_map(|(compact, border)| label_placements.into_iter().map(move |ranks| (compact, border, ranks))) {
+            let options = DisplayOptions { color: false, compact, border, file_labels: (true, true), rank_labels, ..DisplayOptions::default() };
+            let rendered = board.render(None, &None, false, &options, &BTreeMap::new(), None);
+            let lines: Vec<&str> = rendered.lines().filter(|line| !line.is_empty()).collect();
+            let (files, framed) = (lines[0], &lines[1..lines.len() - 1]);
+            let first_letter = display_width(files) - display_width(files.trim_start());
+            let square_start = if rank_labels.0 { RANK_LABEL_WIDTH } else { 0 } + usize::from(border != BorderStyle::None) + usize::from(!compact);
+            // Ranks labelled on the right stick out past the border by the label width
+            let row_width = board.border_line(&options, true).map_or(display_width(framed[0]), |line| display_width(&line))
+                + if rank_labels.1 && border != BorderStyle::None { RANK_LABEL_WIDTH } else { 0 };
+            let ranks = if border == BorderStyle::None { framed } else { &framed[1..framed.len() - 1] };
+            let context = format!("compact {}, {:?} border, rank labels {:?}", compact, border, rank_labels);
+            assert_eq!(ranks.len(), MAX_DIM, "{}", context);
+            assert!(ranks.iter().all(|line| display_width(line) == row_width), "{}", context);
+            assert_eq!(first_letter, square_start, "{}", context);
+        }
+    }
+
+    // The wording players see for each rejected selection or move
+    #[test]
+    fn rejected_moves_are_worded_for_the_player() {
+        let mut game = quiet_game();
+        let message = |result: Result<(), MoveError>| result.unwrap_err().to_string();
+        assert!(game.execute_command("select c3").unwrap().contains("Invalid input: There is no piece at C3."));
+        assert!(game.execute_command("select f6").unwrap().contains("Invalid input: You cannot select a black piece on White's turn."));
+        assert_eq!(message(game.attempt_move(2, 2, 3, 2)), "Invalid move: There is no piece at C3.");
+        assert_eq!(message(game.attempt_move(5, 5, 4, 5)), "Invalid move: You can't move your opponent's piece.");
+        assert_eq!(message(game.attempt_move(0, 1, 0, 1)), "Invalid move: Destination must be different from origin.");
+        assert_eq!(message(game.attempt_move(0, 1, 5, 1)), "Invalid move: ♖ can't move to B6.");
+
+        let mut forced = game_from("O5/6/6/6/6/Do1S2", PlayerColor::White);
+        forced.board.rules.must_capture = true;
+        assert_eq!(message(forced.attempt_move(0, 3, 2, 2)), "Invalid move: A capture is available, so you must capture this turn (A1xC1).");
+        play(&mut forced, &[((0, 0), (0, 2))]);
+        assert_eq!(message(forced.attempt_move(5, 0, 4, 0)), "The game is over. Type 'restart' or 'exit'.");
+    }
+
+    // The variants command lists every rule in the Rules tag, and nothing else
+    #[test]
+    fn variants_list_covers_every_rule() {
+        let tag = Rules::default().to_tag();
+        let keys: Vec<&str> = tag.split_whitespace().map(|item| item.split_once('=').map_or(item, |(key, _)| key)).collect();
+        for key in &keys {
+            assert!(VARIANTS.iter().any(|(tag, _, _)| tag == key), "rule '{}' is missing from VARIANTS", key);
+        }
+        assert_eq!(keys.len(), VARIANTS.len());
+    }
+
+    // A Developer on C3 with enemies on C4 and D4: devortho keeps the jump over C4 but not the one over D4
+    #[test]
+    fn dev_orthogonal_keeps_only_rank_and_file_moves() {
+        let mut board = Board::from_notation("5o/6/2dd2/2D3/6/O5").unwrap();
+        let developer = board.get_piece(2, 2).unwrap();
+        let jumped = |board: &Board| -> Vec<(usize, usize)> {
+            let mut jumped: Vec<(usize, usize)> = board.calculate_valid_moves(2, 2, developer).iter().filter_map(|m| m.jumped_piece_coord).collect();
+            jumped.dedup();
+            jumped
+        };
+        assert!(jumped(&board).contains(&(3, 2)) && jumped(&board).contains(&(3, 3)));
+        assert!(board.calculate_valid_moves(2, 2, developer).iter().any(|m| m.to_r != 2 && m.to_c != 2));
+
+        board.rules.dev_orthogonal = true;
+        assert_eq!(jumped(&board), vec![(3, 2)]);
+        assert!(board.calculate_valid_moves(2, 2, developer).iter().all(|m| m.to_r == 2 || m.to_c == 2));
+    }
+
+    #[test]
+    fn restart_keeps_names_and_variants_but_resets_the_position() {
+        let mut game = GameState { white_name: Some("Ada".to_string()), ..quiet_game() };
+        game.board.rules.must_capture = true;
+        game.settings.verbose = true;
+        play(&mut game, &[((0, 2), (2, 3))]);
+
+        game.reset_position((MIN_DIM, MIN_DIM));
+        assert_eq!(game.white_name.as_deref(), Some("Ada"));
+        assert!(game.board.rules.must_capture && game.settings.verbose);
+        assert!(game.move_history.is_empty() && game.events().is_empty());
+        assert_eq!(game.current_player, PlayerColor::White);
+        assert_eq!(game.board.to_notation(), Board::with_rules(MIN_DIM, MIN_DIM, game.board.rules).to_notation());
+    }
+
+    // The only mate in 1 is the Developer on A1 jumping over the enemy ProductOwner to C1 (the longer
+    // jump to D1 is blocked by the Designer)
+    #[test]
+    fn matein1_finds_the_only_owner_capture() {
+        let board = Board::from_notation("O5/6/6/6/6/Do1S2").unwrap();
+        let mates = board.owner_captures(PlayerColor::White);
+        assert_eq!(mates.len(), 1);
+        let (from, m) = mates[0];
+        assert_eq!((from, (m.to_r, m.to_c), m.jumped_piece_coord), ((0, 0), (0, 2), Some((0, 1))));
+        assert!(board.owner_captures(PlayerColor::Black).is_empty());
+    }
+
+    // One move of each kind, and an illegal one, on a fixed position
+    #[test]
+    fn classify_move_tells_each_kind_apart() {
+        let board = Board::from_notation("O5/d5/6/6/6/Do1S2").unwrap();
+        assert_eq!(board.classify_move((0, 0), (1, 0)), Some(MoveClass::Quiet));
+        assert_eq!(board.classify_move((5, 0), (4, 0)), Some(MoveClass::LandingCapture));
+        assert_eq!(board.classify_move((0, 0), (0, 2)), Some(MoveClass::JumpCapture((0, 1))));
+        assert_eq!(board.classify_move((0, 0), (5, 5)), None);
+    }
+
+    // norepeatpiece blocks the piece that just moved, but not a side's only movable piece
+    #[test]
+    fn norepeatpiece_blocks_the_last_piece_unless_it_is_the_only_one() {
+        for (placement, blocked) in [("O5/6/6/6/6/D4o", Some((4, 0))), ("O5/6/6/6/6/5o", None)] {
+            let mut game = game_from(placement, PlayerColor::White);
+            game.board.rules.no_repeat_piece = true;
+            play(&mut game, &[((5, 0), (4, 0)), ((0, 5), (1, 5))]);
+            assert_eq!(game.repeat_blocked_square(), blocked, "{}", placement);
+            assert!(!game.current_legal_moves().is_empty(), "{}", placement);
+            assert!(game.current_legal_moves().iter().all(|(from, _)| Some(*from) != blocked), "{}", placement);
+        }
+        let mut game = game_from("O5/6/6/6/6/D4o", PlayerColor::White);
+        game.board.rules.no_repeat_piece = true;
+        play(&mut game, &[((5, 0), (4, 0)), ((0, 5), (1, 5))]);
+        assert!(matches!(game.attempt_move(4, 0, 3, 0), Err(MoveError::RepeatPiece(_))));
+    }
+
+    // Black's pieces on a full-size board all stand above the top rank of the smallest one, so a
+    // selection kept across the resize would point off the board
+    #[test]
+    fn selection_does_not_survive_a_resize() {
+        let mut game = GameState { current_player: PlayerColor::Black, quiet: true, ..GameState::new(MAX_DIM, MAX_DIM) };
+        let ((r, c), _) = game.current_legal_moves()[0];
+        game.select_piece(r, c).unwrap();
+        game.reset_position((MIN_DIM, MIN_DIM));
+        assert_eq!(game.selected_square_coords, None);
+        assert!(game.available_moves_for_selected.is_none());
+        assert!(game.validate().is_empty());
+    }
+
+    // The search stops at the time budget with the last depth it completed, so with no time at all it
+    // only gets through depth 1, while a generous budget reaches the full depth
+    #[test]
+    fn generous_time_budget_searches_deeper_than_a_tiny_one() {
+        let start = Board::new(MIN_DIM, MIN_DIM);
+        let searched = |time_budget_ms| {
+            let config = AiConfig { depth: 3, time_budget_ms, ..AiConfig::default() };
+            choose_ai_move(&start, PlayerColor::White, &start.legal_moves(PlayerColor::White), &config).1.depth_reached
+        };
+        assert_eq!(searched(0), 1);
+        assert_eq!(searched(60_000), 3);
+    }
+
+    #[test]
+    fn result_of_a_game_in_progress() {
+        let mut game = quiet_game();
+        assert_eq!(game.result(), GameResult::InProgress);
+        play(&mut game, &[((0, 2), (2, 3))]);
+        assert_eq!(game.result(), GameResult::InProgress);
+    }
+
+    #[test]
+    fn result_when_white_jumps_the_enemy_owner() {
+        let mut game = game_from("O5/6/6/6/6/Do1S2", PlayerColor::White);
+        play(&mut game, &[((0, 0), (0, 2))]);
+        assert_eq!(game.result(), GameResult::WhiteWins);
+    }
+
+    #[test]
+    fn result_when_black_jumps_the_enemy_owner() {
+        let mut game = game_from("dO1s2/6/6/6/6/o5", PlayerColor::Black);
+        play(&mut game, &[((5, 0), (5, 2))]);
+        assert_eq!(game.result(), GameResult::BlackWins);
+    }
+
+    #[test]
+    fn result_of_a_no_progress_draw() {
+        let mut game = quiet_game();
+        game.board.rules.no_progress_limit = Some(1);
+        play(&mut game, &[((0, 2), (2, 3))]);
+        assert_eq!(game.result(), GameResult::Draw);
+    }
+
+    // With the ProductOwner on A1 attacked by the Designer on B3, taking the Developer on F3 still
+    // leaves it hanging, and warnhang says that the capture doesn't help
+    #[test]
+    fn warnhang_calls_out_a_capture_that_leaves_the_owner_attacked() {
+        let game = game_from("5o/6/6/1s3d/6/O3S1", PlayerColor::White);
+        assert_eq!(game.hanging_warning(0, 4, 2, 5).as_deref(), Some("This capture does not resolve the threat to your ProductOwner. Proceed?"));
+        assert_eq!(game.hanging_warning(0, 0, 1, 0), None);
+    }
+
+    // Notation reads back to the same position under either case convention, and the two
+    // conventions differ only in case
+    #[test]
+    fn notation_round_trips_under_either_case_convention() {
+        let board = Board::from_notation("O5/d5/6/6/6/Do1S2").unwrap();
+        for case in [NotationCase::WhiteUpper, NotationCase::BlackUpper] {
+            let written = board.to_notation_with(case);
+            assert_eq!(Board::from_notation_with(&written, case).unwrap().to_notation(), "O5/d5/6/6/6/Do1S2", "{:?}", case);
+        }
+        assert_eq!(board.to_notation_with(NotationCase::BlackUpper), "o5/D5/6/6/6/dO1s2");
+        assert_eq!(Board::from_notation_with("o5/D5/6/6/6/dO1s2", NotationCase::BlackUpper).unwrap().get_piece(0, 0),
+            Some(Piece::new(PieceType::Developer, PlayerColor::White)));
+    }
+
+    // A game started from a setup position replays from there, even after resuming from its autosave
+    #[test]
+    fn resume_rebuilds_a_game_started_from_an_edited_position() {
+        let mut game = quiet_game();
+        for command in ["setup empty", "put O white a1", "put D white c3", "put O black f6", "put S black e4", "done black"] {
+            game.execute_command(command).unwrap();
+        }
+        play(&mut game, &[((3, 4), (1, 3)), ((2, 2), (4, 2))]);
+        let file = std::env::temp_dir().join(format!("unvoid-autosave-test-{}.txt", std::process::id()));
+        game.autosave_file = Some(file.to_string_lossy().into_owned());
+        game.autosave = true;
+        game.write_autosave();
+
+        let resumed = GameState::from_transcript(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(resumed.board.to_notation(), game.board.to_notation());
+        assert_eq!(resumed.move_history.len(), 2);
+        assert_eq!(resumed.current_player, game.current_player);
+        assert_eq!(resumed.starting_board().to_notation(), "5o/6/4s1/2D3/6/O5");
+    }
+
+    // After turn changes sides mid-game the moves no longer replay, so nothing is autosaved
+    #[test]
+    fn autosave_skips_a_game_edited_mid_game() {
+        let mut game = quiet_game();
+        play(&mut game, &[((0, 2), (2, 3))]);
+        game.execute_command("analysis on").unwrap();
+        game.execute_command("turn white").unwrap();
+        let file = std::env::temp_dir().join(format!("unvoid-edited-test-{}.txt", std::process::id()));
+        game.autosave_file = Some(file.to_string_lossy().into_owned());
+        assert!(game.execute_command("autosave on").unwrap().contains("can't be rebuilt"));
+        game.autosave = true;
+        game.write_autosave();
+        assert!(!file.exists());
+    }
+
+    // After the Designer's move the Developer on A1 can still jump the ProductOwner, which steps to
+    // A2 and is jumped there
+    #[test]
+    fn scripted_game_logs_its_events_in_order() {
+        let mut game = game_from("O5/6/6/6/6/Do1S2", PlayerColor::White);
+        play(&mut game, &[((0, 3), (2, 2)), ((0, 1), (1, 0)), ((0, 0), (2, 0))]);
+        let (developer, designer, owner) = (Piece::new(PieceType::Developer, PlayerColor::White), Piece::new(PieceType::Designer, PlayerColor::White),
+            Piece::new(PieceType::ProductOwner, PlayerColor::Black));
+        assert_eq!(game.events(), [
+            GameEvent::MoveMade { player: PlayerColor::White, piece: designer, from: (0, 3), to: (2, 2) },
+            GameEvent::OwnerAttacked { by: PlayerColor::White },
+            GameEvent::MoveMade { player: PlayerColor::Black, piece: owner, from: (0, 1), to: (1, 0) },
+            GameEvent::MoveMade { player: PlayerColor::White, piece: developer, from: (0, 0), to: (2, 0) },
+            GameEvent::CaptureMade { player: PlayerColor::White, captured: owner, square: (1, 0) },
+            GameEvent::GameEnded { result: GameResult::WhiteWins, reason: EndReason::OwnerCaptured },
+        ]);
+        assert_eq!(game.captures_by(PlayerColor::White), 1);
+    }
+
+    // A takeback drops the taken-back move's own events and records that it happened
+    #[test]
+    fn takeback_replaces_the_move_events_in_the_log() {
+        let mut game = quiet_game();
+        play(&mut game, &[((0, 2), (2, 3))]);
+        game.take_back();
+        assert_eq!(game.events(), [GameEvent::MoveTakenBack { player: PlayerColor::White }]);
+    }
+
+    #[test]
+    fn setup_problems_check_owners_and_the_side_to_move() {
+        let board = Board::from_notation("O5/6/6/6/6/5o").unwrap();
+        assert!(board.setup_problems(PlayerColor::White).is_empty());
+        let mut no_owner = board.clone();
+        no_owner.set_square(0, 5, None);
+        assert_eq!(no_owner.setup_problems(PlayerColor::White), ["Black has 0 ProductOwners (expected 1)."]);
+    }
+
+    // Under strict, taking the Developer on F3 is refused because the Designer on B3 still attacks
+    // the ProductOwner on A1, with a reason that names the threat; moving the ProductOwner is fine
+    #[test]
+    fn strict_rejects_a_capture_that_leaves_the_owner_attacked() {
+        let mut game = game_from("5o/6/6/1s3d/6/O3S1", PlayerColor::White);
+        game.board.rules.strict = true;
+        let err = game.attempt_move(0, 4, 2, 5).unwrap_err();
+        assert_eq!(err, MoveError::ExposesOwner { capture: true });
+        assert_eq!(err.to_string(), "Invalid move: This capture does not resolve the threat to your ProductOwner (strict).");
+        assert!(!destinations(game.legal_moves_from(0, 4, Piece::new(PieceType::Designer, PlayerColor::White))).contains(&(2, 5)));
+        assert!(game.attempt_move(0, 0, 1, 0).is_ok());
+    }
+
+    // Random games on every board size never give two different positions the same key. Sizes are
+    // kept apart, since the transposition table never mixes them.
+    #[test]
+    fn random_games_have_no_zobrist_collisions() {
+        let mut rng = Rng::new(7);
+        let mut position_by_key: HashMap<(usize, usize, u64), String> = HashMap::new();
+        for _ in 0..60 {
+            let (width, height) = (MIN_DIM + rng.below(MAX_DIM - MIN_DIM + 1), MIN_DIM + rng.below(MAX_DIM - MIN_DIM + 1));
+            let mut board = Board::new(width, height);
+            let mut color = PlayerColor::White;
+            for _ in 0..MAX_VERIFY_PLIES {
+                let position = position_with_side(&board, color);
+                let other = position_by_key.entry((width, height, board.position_key(color))).or_insert_with(|| position.clone());
+                assert_eq!(*other, position, "same key on a {}x{} board", width, height);
+                let moves = board.legal_moves(color);
+                if moves.is_empty() { break; }
+                let (from, m) = moves[rng.below(moves.len())];
+                if board.apply_move(from.0, from.1, &m).is_some_and(|p| p.piece_type == PieceType::ProductOwner) { break; }
+                color = color.opponent();
+            }
+        }
+        assert!(position_by_key.len() > 1000);
+    }
+
+    // Taking a move back restores the pieces but keeps a rule changed after the move
+    #[test]
+    fn takeback_keeps_rules_changed_after_the_move() {
+        let mut game = quiet_game();
+        let start = game.board.to_notation();
+        play(&mut game, &[((0, 2), (2, 3))]);
+        game.execute_command("designer extended").unwrap();
+        game.execute_command("mustcapture on").unwrap();
+        game.take_back();
+        assert_eq!(game.board.to_notation(), start);
+        assert_eq!(game.board.hash(), game.board.compute_hash());
+        assert!(game.board.rules.must_capture);
+        assert_eq!(game.board.rules.designer_shape, DesignerShape::Extended);
+        let designer = Piece::new(PieceType::Designer, PlayerColor::White);
+        assert_eq!(destinations(game.board.calculate_valid_moves(0, 2, designer)), destinations(game.board.reference_valid_moves(0, 2, designer)));
+    }
+
+    // '#' marks the capture that won, and only that: with suddendeath off, taking the ProductOwner
+    // leaves the game going and gets no '#'
+    #[test]
+    fn only_the_winning_capture_is_marked_with_a_hash() {
+        let mut game = game_from("5o/6/4S1/6/6/O5", PlayerColor::White);
+        play(&mut game, &[((3, 4), (5, 5))]);
+        assert_eq!(game.result(), GameResult::WhiteWins);
+        assert!(game.to_transcript().contains("1. E4xF6# 1-0"), "{}", game.to_transcript());
+
+        let mut play_on = game_from("5o/d5/4S1/6/6/O5", PlayerColor::White);
+        play_on.board.rules.play_on = true;
+        play(&mut play_on, &[((3, 4), (5, 5))]);
+        assert_eq!(play_on.result(), GameResult::InProgress);
+        assert_eq!(play_on.move_history[0].square_notation(MIN_DIM, 1), "E4xF6");
+    }
+
+    // Each game keeps its own display options, even when two of them take turns running commands
+    #[test]
+    fn display_options_stay_with_their_game() {
+        let mut plain = GameState { quiet: true, ..GameState::new(MIN_DIM, 10) };
+        let mut styled = GameState { quiet: true, ..GameState::new(MIN_DIM, 10) };
+        styled.execute_command("theme ascii").unwrap();
+        styled.execute_command("coordpad on").unwrap();
+        styled.execute_command("zeroranks on").unwrap();
+        assert!(plain.execute_command("select b1").unwrap().contains("Selected: ♖ at B1."));
+        assert!(styled.execute_command("select b00").unwrap().contains("Selected: D at B00."));
+        assert!(plain.execute_command("select b1").unwrap().contains("Selected: ♖ at B1."));
+    }
+
+    // A Developer on any edge square of the smallest and largest boards finds its moves without
+    // stepping off the board, and a step straight off the edge is reported as off-board
+    #[test]
+    fn developer_on_the_edge_stays_on_the_board() {
+        let developer = Piece::new(PieceType::Developer, PlayerColor::White);
+        for (width, height) in [(MIN_DIM, MIN_DIM), (MAX_DIM, MIN_DIM), (MIN_DIM, MAX_DIM)] {
+            let mut board = Board::empty(width, height);
+            for (r, c) in (0..height).flat_map(|r| (0..width).map(move |c| (r, c))) {
+                if r != 0 && c != 0 && r != height - 1 && c != width - 1 { continue; }
+                board.set_square(r, c, Some(developer));
+                let moves = board.calculate_valid_moves(r, c, developer);
+                assert!(!moves.is_empty() && moves.iter().all(|m| m.to_r < height && m.to_c < width), "{}x{} {:?}", width, height, (r, c));
+                let outward = (if r == 0 { -1 } else { 1 }, if c == 0 { -1 } else { 1 });
+                assert!(matches!(board.developer_step(r, c, developer, outward.0, outward.1, 3), DeveloperStep::OffBoard));
+                board.set_square(r, c, None);
             }
-            _ => {
-                println!("Unknown command: {}", command);
-                println!("Type \"help\" to see a list of valid commands.");
+        }
+    }
+
+    // The Developer on A1 can only jump the enemy on B2; with dev_land_capture it may also land on it,
+    // and the jump over it is still there
+    #[test]
+    fn dev_land_capture_adds_the_adjacent_capture_only_when_on() {
+        let mut board = Board::from_notation("5o/6/6/6/1d4/D4O").unwrap();
+        let developer = Piece::new(PieceType::Developer, PlayerColor::White);
+        let jump = MoveDetail { to_r: 2, to_c: 2, is_capture: true, jumped_piece_coord: Some((1, 1)) };
+        let land = MoveDetail { to_r: 1, to_c: 1, is_capture: true, jumped_piece_coord: None };
+        for land_capture in [false, true] {
+            board.rules.dev_land_capture = land_capture;
+            let moves = board.calculate_valid_moves(0, 0, developer);
+            assert!(moves.contains(&jump), "{}", land_capture);
+            assert_eq!(moves.contains(&land), land_capture);
+        }
+    }
+
+    // The extended Designer adds the 1-3 L to the standard 1-2 L: all 16 squares from the centre of
+    // a 12x12 board, and only the on-board ones from a corner and an edge
+    #[test]
+    fn extended_designer_reaches_the_long_l_squares_on_the_board() {
+        let designer = Piece::new(PieceType::Designer, PlayerColor::White);
+        let mut board = Board::empty(MAX_DIM, MAX_DIM);
+        board.set_designer_shape(DesignerShape::Extended);
+        assert_eq!(board.calculate_valid_moves(5, 5, designer).len(), 16);
+        assert_eq!(destinations(board.calculate_valid_moves(0, 0, designer)), [(1, 2), (1, 3), (2, 1), (3, 1)]);
+        assert_eq!(destinations(board.calculate_valid_moves(0, 5, designer)),
+            [(1, 2), (1, 3), (1, 7), (1, 8), (2, 4), (2, 6), (3, 4), (3, 6)]);
+        board.set_designer_shape(DesignerShape::Standard);
+        assert_eq!(destinations(board.calculate_valid_moves(0, 0, designer)), [(1, 2), (2, 1)]);
+    }
+
+    // Under mustcapture, White's Developer can jump the Developer on A2, so a quiet ProductOwner move
+    // is refused with the capture spelled out, and the capture is accepted
+    #[test]
+    fn mustcapture_forces_the_available_capture() {
+        let mut game = game_from("5o/6/6/6/d5/D4O", PlayerColor::White);
+        game.board.rules.must_capture = true;
+        assert!(game.legal_moves_from(0, 5, Piece::new(PieceType::ProductOwner, PlayerColor::White)).is_empty());
+        let err = game.attempt_move(0, 5, 1, 5).unwrap_err();
+        assert_eq!(err, MoveError::MustCapture("A1xA3, A1xA4".to_string()));
+        assert_eq!(err.to_string(), "Invalid move: A capture is available, so you must capture this turn (A1xA3, A1xA4).");
+        play(&mut game, &[((0, 0), (2, 0))]);
+        assert_eq!(game.board.get_piece(1, 0), None);
+    }
+
+    // The mirrored setup is the standard one reflected left to right, with one ProductOwner a side
+    #[test]
+    fn mirrored_setup_reflects_the_standard_one() {
+        for (width, height) in [(MIN_DIM, MIN_DIM), (9, 7), (MAX_DIM, MAX_DIM)] {
+            let standard = Board::new(width, height);
+            let mirrored = Board::with_rules(width, height, Rules { setup_layout: SetupLayout::Mirrored, ..Rules::default() });
+            for (r, c) in (0..height).flat_map(|r| (0..width).map(move |c| (r, c))) {
+                assert_eq!(mirrored.grid[r][c], standard.grid[r][width - 1 - c], "{}x{} {:?}", width, height, (r, c));
             }
+            assert!(mirrored.setup_problems(PlayerColor::White).is_empty());
         }
-        println!();
     }
-}
 
+    // A move is read from two squares or from one token joined by '-' or 'x'; only 'x' marks a capture
+    #[test]
+    fn move_arguments_accept_dash_and_x_tokens() {
+        assert_eq!(parse_move_args(&["B1", "C3"]), Some(("B1", "C3", false)));
+        assert_eq!(parse_move_args(&["B1-C3"]), Some(("B1", "C3", false)));
+        assert_eq!(parse_move_args(&["B1xC3"]), Some(("B1", "C3", true)));
+        assert_eq!(parse_move_args(&["b1XC3"]), Some(("b1", "C3", true)));
+        assert_eq!(parse_move_args(&["B1-"]), None);
+        assert_eq!(parse_move_args(&["B1C3"]), None);
+        assert_eq!(parse_move_args(&["B1", "C3", "D4"]), None);
+        let mut game = quiet_game();
+        assert!(game.execute_command("move C1xD3").unwrap().contains("is written as a capture"));
+        game.execute_command("move C1-D3").unwrap();
+        assert_eq!(game.move_history.len(), 1);
+    }
+
+    // With wincaptures 2, White's first capture only counts towards the target and the second wins
+    #[test]
+    fn capture_target_wins_on_the_last_capture_needed() {
+        let mut game = game_from("5o/6/d5/6/d5/D4O", PlayerColor::White);
+        game.board.rules.capture_target = Some(2);
+        play(&mut game, &[((0, 0), (2, 0)), ((5, 5), (5, 4))]);
+        assert_eq!(game.captures_by(PlayerColor::White), 1);
+        assert_eq!(game.result(), GameResult::InProgress);
+        play(&mut game, &[((2, 0), (4, 0))]);
+        assert_eq!(game.ending(), Some((GameResult::WhiteWins, EndReason::CaptureTarget(2))));
+    }
+
+    // The search takes the ProductOwner rather than the free Developer, at any depth, and the side
+    // whose ProductOwner is attacked moves it somewhere safe
+    #[test]
+    fn ai_takes_the_owner_and_gets_its_own_out_of_danger() {
+        let board = Board::from_notation("5o/6/4S1/6/d5/D4O").unwrap();
+        for depth in [1, 3] {
+            let config = AiConfig { depth, time_budget_ms: 60_000, ..AiConfig::default() };
+            let (choice, _) = choose_ai_move(&board, PlayerColor::White, &board.legal_moves(PlayerColor::White), &config);
+            let ((from_r, from_c), m) = choice.expect("a move");
+            assert_eq!(((from_r, from_c), (m.to_r, m.to_c)), ((3, 4), (5, 5)), "depth {}", depth);
+        }
+
+        let mut board = Board::from_notation("5o/6/4S1/6/6/O5").unwrap();
+        let config = AiConfig { depth: 2, time_budget_ms: 60_000, ..AiConfig::default() };
+        let (choice, _) = choose_ai_move(&board, PlayerColor::Black, &board.legal_moves(PlayerColor::Black), &config);
+        let ((from_r, from_c), m) = choice.expect("a move");
+        board.apply_move(from_r, from_c, &m);
+        assert!(board.owner_captures(PlayerColor::White).is_empty());
+    }
+
+    // On the opening position at depth 4, remembering searched positions visits fewer nodes
+    #[test]
+    fn transposition_table_cuts_the_node_count() {
+        let board = Board::new(MIN_DIM, MIN_DIM);
+        let moves = board.legal_moves(PlayerColor::White);
+        let search = |use_table| {
+            let config = AiConfig { depth: 4, use_table, time_budget_ms: 60_000, ..AiConfig::default() };
+            choose_ai_move(&board, PlayerColor::White, &moves, &config).1
+        };
+        let (cached, uncached) = (search(true), search(false));
+        assert_eq!((cached.depth_reached, uncached.depth_reached), (4, 4));
+        assert!(cached.table_hits > 0);
+        assert!(cached.nodes < uncached.nodes, "{} vs {} nodes", cached.nodes, uncached.nodes);
+    }
+
+    // fill_valid_moves gives the same list as calculate_valid_moves, for every piece through a
+    // random game, with one buffer reused (and left full) from piece to piece
+    #[test]
+    fn buffer_moves_match_owned_moves() {
+        let mut rng = Rng::new(3);
+        let mut board = Board::with_rules(8, 10, Rules { dev_land_capture: true, designer_shape: DesignerShape::Extended, ..Rules::default() });
+        let mut buffer = Vec::new();
+        let mut color = PlayerColor::White;
+        for _ in 0..40 {
+            for (r, c) in (0..board.height).flat_map(|r| (0..board.width).map(move |c| (r, c))) {
+                let Some(piece) = board.grid[r][c] else { continue; };
+                board.fill_valid_moves(r, c, piece, &mut buffer);
+                assert_eq!(buffer, board.calculate_valid_moves(r, c, piece));
+            }
+            let moves = board.legal_moves(color);
+            if moves.is_empty() { break; }
+            let (from, m) = moves[rng.below(moves.len())];
+            if board.apply_move(from.0, from.1, &m).is_some_and(|p| p.piece_type == PieceType::ProductOwner) { break; }
+            color = color.opponent();
+        }
+    }
+
+    // A search spawned on a copy of the board sends back one of the moves it was given
+    #[test]
+    fn spawned_search_sends_back_a_move() {
+        let board = Board::new(MIN_DIM, MIN_DIM);
+        let moves = board.legal_moves(PlayerColor::White);
+        let receiver = spawn_search(&board, PlayerColor::White, moves.clone(), AiConfig { depth: 2, ..AiConfig::default() });
+        let (choice, stats) = receiver.recv_timeout(Duration::from_secs(30)).expect("the search answers");
+        assert!(moves.contains(&choice.expect("a move")));
+        assert!(stats.nodes > 0);
+    }
+
+    // Previewing a move, even one that would win, describes it but leaves the game as it was
+    #[test]
+    fn preview_leaves_the_game_untouched() {
+        let mut game = game_from("5o/6/4S1/6/6/O5", PlayerColor::White);
+        let before = (game.board.to_notation(), game.board.hash(), game.current_player);
+        let output = game.execute_command("move E4 F6 --dry").unwrap();
+        assert!(output.contains("Preview: ♘ E4xF6, captures ♚ and wins the game."), "{}", output);
+        assert!(game.execute_command("preview E4-C5").unwrap().contains("Preview: ♘ E4-C5."));
+        assert_eq!((game.board.to_notation(), game.board.hash(), game.current_player), before);
+        assert!(game.move_history.is_empty() && game.events().is_empty());
+        assert_eq!(game.result(), GameResult::InProgress);
+    }
+
+    // move and select say whether an argument is missing or extra, and extra spaces or capitals in
+    // a well-formed command don't matter
+    #[test]
+    fn malformed_move_and_select_say_too_few_or_too_many() {
+        let mut game = quiet_game();
+        for (command, expected) in [
+            ("move", "Too few arguments. The 'move' command requires <from> and <to> coordinates."),
+            ("move C1", "Too few arguments. The 'move' command requires <from> and <to> coordinates."),
+            ("move C1 D3 extra", "Too many arguments. The 'move' command takes only <from> and <to> coordinates."),
+            ("select", "Too few arguments. The 'select' command needs a square."),
+            ("select C1 D3", "Too many arguments. The 'select' command takes only one coordinate."),
+        ] {
+            assert!(game.execute_command(command).unwrap().contains(expected), "{}", command);
+        }
+        assert!(game.move_history.is_empty());
+        game.execute_command("  MOVE  C1   D3  ").unwrap();
+        assert_eq!(game.move_history.len(), 1);
+    }
+
+    // closesetup on a tall 6x12 board puts Black's back rank that many rows above White's, and a gap
+    // bigger than the board falls back to the top edge
+    #[test]
+    fn closesetup_brings_black_down_on_a_tall_board() {
+        let back_rank_rows = |board: &Board, color| -> Vec<usize> {
+            let mut rows: Vec<usize> = (0..board.height).filter(|&r| board.grid[r].iter().flatten().any(|p| p.color == color)).collect();
+            rows.dedup();
+            rows
+        };
+        for (gap, black_row) in [(3, 3), (1, 1), (20, 11)] {
+            let board = Board::with_rules(6, 12, Rules { rank_gap: Some(gap), ..Rules::default() });
+            assert_eq!(back_rank_rows(&board, PlayerColor::White), [0]);
+            assert_eq!(back_rank_rows(&board, PlayerColor::Black), [black_row], "gap {}", gap);
+            assert!(board.setup_problems(PlayerColor::White).is_empty(), "gap {}", gap);
+        }
+    }
+
+    // A selection made before the board was edited is refused once, then the move goes through
+    // against the current position
+    #[test]
+    fn stale_selection_is_refused_after_an_edit() {
+        let mut game = quiet_game();
+        game.select_piece(0, 2).unwrap();
+        game.board.set_square(2, 3, Some(Piece::new(PieceType::Developer, PlayerColor::White)));
+        assert_eq!(game.attempt_move(0, 2, 1, 4), Err(MoveError::StaleSelection));
+        assert_eq!(game.selected_square_coords, None);
+        assert!(matches!(game.attempt_move(0, 2, 2, 3), Err(MoveError::IllegalDestination { .. })));
+        play(&mut game, &[((0, 2), (1, 4))]);
+    }
+
+    // The handicap piece stands fourth from its side's corner, next to the usual three, and can move
+    #[test]
+    fn handicap_piece_is_placed_and_can_move() {
+        let mut game = quiet_game();
+        game.board = Board::with_rules(MIN_DIM, MIN_DIM, Rules { handicap: Some((PlayerColor::White, PieceType::Developer)), ..Rules::default() });
+        assert_eq!(game.board.get_piece(0, 3), Some(Piece::new(PieceType::Developer, PlayerColor::White)));
+        assert_eq!(game.board.count_pieces(PlayerColor::White, PieceType::Developer), 2);
+        assert!(game.board.setup_problems(PlayerColor::White).is_empty());
+        play(&mut game, &[((0, 3), (3, 3))]);
+
+        let board = Board::with_rules(MIN_DIM, MIN_DIM, Rules { handicap: Some((PlayerColor::Black, PieceType::Designer)), ..Rules::default() });
+        assert_eq!(board.get_piece(5, 2), Some(Piece::new(PieceType::Designer, PlayerColor::Black)));
+        assert_eq!(board.count_pieces(PlayerColor::Black, PieceType::ProductOwner), 1);
+    }
+
+    // The square range in size and in the bad-square hint follows zeroranks and coordpad
+    #[test]
+    fn square_ranges_follow_the_rank_numbering() {
+        let mut game = quiet_game();
+        assert!(game.execute_command("size").unwrap().contains("Squares: A1 to F6"));
+        game.execute_command("zeroranks on").unwrap();
+        assert!(game.execute_command("size").unwrap().contains("Squares: A0 to F5"));
+        assert!(game.execute_command("select a6").unwrap().contains("Please enter coordinates from A0 to F5."));
+        let mut tall = GameState { quiet: true, ..GameState::new(MIN_DIM, MAX_DIM) };
+        tall.execute_command("coordpad on").unwrap();
+        assert!(tall.execute_command("size").unwrap().contains("Squares: A01 to F12"));
+    }
+
+    // A host driving execute_command gets the board and turn line after each command, and the
+    // computer's reply when it is to move
+    #[test]
+    fn execute_command_shows_the_position_and_the_ai_reply() {
+        let mut game = quiet_game();
+        game.ai_config.depth = 1;
+        let output = game.execute_command("move C1 D3").unwrap();
+        assert!(output.contains("Turn: Black"), "{}", output);
+        assert!(output.contains(" 1| ♔  ♖    "), "{}", output);
+        game.execute_command("ai black").unwrap();
+        assert_eq!(game.current_player, PlayerColor::White);
+        assert_eq!(game.move_history.len(), 2);
+        assert!(game.position_view().contains("Turn: White"));
+    }
+}